@@ -21,6 +21,8 @@ ACTION_MAP = {
     5: "FORWARD",
     6: "BACKWARD",
     7: "SHOOT",
+    8: "STRAFE_LEFT",
+    9: "STRAFE_RIGHT",
 }
 
 
@@ -34,7 +36,7 @@ class Environment:
             shape=(self.number_of_agents, size[0], size[1], 1),
             dtype=np.uint8,
         )
-        self.action_space = spaces.Discrete(8)
+        self.action_space = spaces.Discrete(len(ACTION_MAP))
         self.metadata = {}
         self.images = []
 
@@ -11,5 +11,9 @@ bitflags! {
         const TURN_LEFT = 1 << 5;
         const TURN_RIGHT = 1 << 6;
         const SHOOT = 1 << 7;
+        /// Move left while turning right, circling a point ahead; `LEFT` only moves
+        const STRAFE_LEFT = 1 << 8;
+        /// Move right while turning left, circling a point ahead; `RIGHT` only moves
+        const STRAFE_RIGHT = 1 << 9;
     }
 }
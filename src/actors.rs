@@ -6,12 +6,12 @@ use bevy_mod_raycast::{RaycastMesh, RaycastSource};
 use bevy_rapier3d::prelude::*;
 use bevy_rl::*;
 
-use names::Generator;
-use rand::{prelude::SliceRandom, thread_rng, Rng};
+use names::{ADJECTIVES, NOUNS};
+use rand::{prelude::SliceRandom, Rng};
 use serde::Serialize;
 
 use crate::gym::EnvironmentState;
-use crate::{actions::*, game::*, level::*};
+use crate::{actions::*, game::*, level::*, settings::*};
 
 // Components
 
@@ -21,17 +21,49 @@ pub struct Actor {
     pub rotation: f32,
     pub name: String,
     pub health: u16,
+    pub regenerating: bool,
+    /// Shots fired and shots that damaged another agent in the current episode
+    pub shots: u32,
+    pub hits: u32,
+    /// Name of the agent that last damaged this one, credited with the kill
+    pub last_damager: Option<String>,
+    /// Agents killed in the current episode
+    pub kills: u32,
 }
 
+/// Health regeneration bookkeeping, see `GameSettings::health_regen`
+#[derive(Component, Default)]
+pub(crate) struct Regeneration {
+    last_health: u16,
+    since_damage: f32,
+    restored: f32,
+}
+
+/// Phase of the camera view bob, see `GameSettings::view_bob`
+#[derive(Component, Default)]
+pub(crate) struct ViewBobPhase(f32);
+
+/// Marks an actor whose health reached zero; it ignores actions until reset
+#[derive(Component)]
+pub(crate) struct Dead;
+
+/// Index of the agent in bevy_rl actions, rewards and observations.
+/// Query order of actors changes when components are inserted, so never index agents by it.
+#[derive(Component, Clone, Copy)]
+pub(crate) struct AgentIndex(pub(crate) usize);
+
 // Bundles
 
 #[derive(Bundle)]
 pub(crate) struct ActorBundle {
     collider: Collider,
+    friction: Friction,
+    restitution: Restitution,
     actor: Actor,
     rigid_body: RigidBody,
     locked_axes: LockedAxes,
     velocity: Velocity,
+    regeneration: Regeneration,
     #[bundle]
     spacial_bundle: SpatialBundle,
 }
@@ -44,15 +76,60 @@ struct ActorWeaponBundle {
 }
 
 // Constructors
-fn new_agent_bundle(game_map: GameMap, actor_name: String) -> ActorBundle {
-    let mut rng = thread_rng();
-    let pos = game_map.empty_space.choose(&mut rng).unwrap();
+fn new_agent_collider(actor_collider: &ActorCollider) -> Collider {
+    match *actor_collider {
+        ActorCollider::Ball { radius } => Collider::ball(radius),
+        ActorCollider::Capsule {
+            half_height,
+            radius,
+        } => Collider::capsule_y(half_height, radius),
+    }
+}
+
+/// Random `adjective-noun` name, same format as `names::Generator`
+fn new_agent_name(rng: &mut impl Rng) -> String {
+    format!(
+        "{}-{}",
+        ADJECTIVES.choose(rng).unwrap(),
+        NOUNS.choose(rng).unwrap()
+    )
+}
+
+fn new_agent_bundle(
+    game_map: &GameMap,
+    actor_name: String,
+    settings: &GameSettings,
+    spawn_pose: Option<&SpawnPose>,
+    rng: &mut impl Rng,
+) -> ActorBundle {
+    let (position, rotation) = match spawn_pose {
+        Some(pose) => ((pose.x, pose.z), pose.rotation),
+        None => {
+            let pos = settings
+                .spawn_candidates(game_map)
+                .choose(rng)
+                .copied()
+                .unwrap();
+            (
+                (
+                    pos.0 as f32 * settings.cell_size,
+                    pos.1 as f32 * settings.cell_size,
+                ),
+                rng.gen_range(0.0..std::f32::consts::PI * 2.0),
+            )
+        }
+    };
 
     let actor = Actor {
-        position: (pos.0 as f32, pos.1 as f32),
-        rotation: rng.gen_range(0.0..std::f32::consts::PI * 2.0),
+        position,
+        rotation,
         name: actor_name,
-        health: 100,
+        health: settings.starting_health,
+        regenerating: false,
+        shots: 0,
+        hits: 0,
+        last_damager: None,
+        kills: 0,
     };
 
     ActorBundle {
@@ -66,14 +143,20 @@ fn new_agent_bundle(game_map: GameMap, actor_name: String) -> ActorBundle {
             ..Default::default()
         },
         velocity: Velocity { ..default() },
-        collider: Collider::ball(1.0),
+        regeneration: Regeneration::default(),
+        collider: new_agent_collider(&settings.actor_collider),
+        friction: Friction::coefficient(settings.actor_friction),
+        restitution: Restitution::coefficient(settings.actor_restitution),
         rigid_body: RigidBody::Dynamic,
         actor,
         locked_axes: (LockedAxes::ROTATION_LOCKED_X | LockedAxes::ROTATION_LOCKED_Z),
     }
 }
 
-fn new_agent_camera_bundle(render_target: RenderTarget) -> ActorWeaponBundle {
+fn new_agent_camera_bundle(
+    render_target: RenderTarget,
+    settings: &GameSettings,
+) -> ActorWeaponBundle {
     ActorWeaponBundle {
         camera_bundle: Camera3dBundle {
             camera: Camera {
@@ -85,6 +168,12 @@ fn new_agent_camera_bundle(render_target: RenderTarget) -> ActorWeaponBundle {
                 clear_color: ClearColorConfig::Custom(Color::WHITE),
                 ..default()
             },
+            projection: Projection::Perspective(PerspectiveProjection {
+                near: settings.camera_near,
+                far: settings.camera_far,
+                ..default()
+            }),
+            tonemapping: settings.tonemapping.into(),
             ..default()
         },
         raycast_source: RaycastSource::<RaycastMarker>::new_transform_empty(),
@@ -95,6 +184,8 @@ fn new_agent_camera_bundle(render_target: RenderTarget) -> ActorWeaponBundle {
 pub(crate) fn spawn_computer_actors(
     mut commands: Commands,
     game_map: Res<GameMap>,
+    settings: Res<GameSettings>,
+    mut env_rng: ResMut<EnvRng>,
     ai_gym_state: Res<AIGymState<Actions, EnvironmentState>>,
 
     mut meshes: ResMut<Assets<Mesh>>,
@@ -102,7 +193,16 @@ pub(crate) fn spawn_computer_actors(
 ) {
     let mut ai_gym_state = ai_gym_state.lock().unwrap();
     let ai_gym_settings = ai_gym_state.settings.clone();
-    let material = materials.add(Color::RED.into());
+    let material = if settings.highlight_agents {
+        materials.add(StandardMaterial {
+            base_color: Color::YELLOW,
+            emissive: Color::YELLOW,
+            unlit: true,
+            ..default()
+        })
+    } else {
+        materials.add(Color::RED.into())
+    };
     let mesh = meshes.add(Mesh::from(shape::UVSphere {
         sectors: 128,
         stacks: 64,
@@ -111,28 +211,238 @@ pub(crate) fn spawn_computer_actors(
 
     let mut actors: Vec<Actor> = Vec::new();
     for i in 0..ai_gym_settings.num_agents {
-        let agent_bundle = new_agent_bundle(game_map.clone(), Generator::default().next().unwrap());
+        let actor_name = new_agent_name(&mut env_rng.0);
+        let spawn_pose = settings
+            .spawn_plan
+            .as_ref()
+            .and_then(|plan| plan.get(i as usize));
+        let agent_bundle =
+            new_agent_bundle(&game_map, actor_name, &settings, spawn_pose, &mut env_rng.0);
 
         actors.push(agent_bundle.actor.clone());
-        commands.spawn(agent_bundle).with_children(|cell| {
-            // Agent model
-            cell.spawn(PbrBundle {
-                mesh: mesh.clone(),
-                material: material.clone(),
-                transform: Transform::from_scale(Vec3::splat(0.33)),
-                ..default()
-            })
-            .insert(RaycastMesh::<RaycastMarker>::default());
-            // Camera
-            let agent_camera_bundle: ActorWeaponBundle = new_agent_camera_bundle(
-                RenderTarget::Image(ai_gym_state.render_image_handles[i as usize].clone()),
-            );
-            cell.spawn(agent_camera_bundle);
-        });
+        commands
+            .spawn((agent_bundle, AgentIndex(i as usize)))
+            .with_children(|cell| {
+                // Agent model
+                cell.spawn(PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_scale(Vec3::splat(0.33)),
+                    ..default()
+                })
+                .insert(RaycastMesh::<RaycastMarker>::default());
+                // Camera
+                let agent_camera_bundle: ActorWeaponBundle = new_agent_camera_bundle(
+                    RenderTarget::Image(ai_gym_state.render_image_handles[i as usize].clone()),
+                    &settings,
+                );
+                let mut camera = cell.spawn((agent_camera_bundle, ViewBobPhase::default()));
+                if let Some(fog) = &settings.fog {
+                    camera.insert(FogSettings {
+                        color: Color::rgb(fog.color[0], fog.color[1], fog.color[2]),
+                        falloff: FogFalloff::Exponential {
+                            density: fog.density,
+                        },
+                        ..default()
+                    });
+                }
+            });
     }
     let env_state = EnvironmentState {
         map: game_map.clone(),
         actors,
+        raw_rewards: vec![0.0; ai_gym_settings.num_agents as usize],
+        ..default()
     };
     ai_gym_state.set_env_state(env_state);
 }
+
+/// Keep serialized actor pose in sync with physics; `rotation` is the heading in `[0, 2π)`
+pub(crate) fn sync_actor_transforms(mut actor_query: Query<(&Transform, &mut Actor)>) {
+    for (transform, mut actor) in actor_query.iter_mut() {
+        let (yaw, _, _) = transform.rotation.to_euler(EulerRot::YXZ);
+
+        actor.position = (transform.translation.x, transform.translation.z);
+        actor.rotation = yaw.rem_euclid(std::f32::consts::PI * 2.0);
+    }
+}
+
+/// Fraction of full speed below which an agent counts as idle for view bob
+const VIEW_BOB_IDLE_SPEED: f32 = 0.05;
+
+/// Advance the view bob phase by `dt` seconds at `speed`, a fraction of full speed.
+/// Idle agents ease the phase to the nearest point where the camera offset is zero.
+fn advance_view_bob_phase(phase: f32, speed: f32, view_bob: &ViewBob, dt: f32) -> f32 {
+    let step = std::f32::consts::TAU * view_bob.frequency * dt;
+    let phase = if speed > VIEW_BOB_IDLE_SPEED {
+        phase + step * speed
+    } else {
+        let rest = (phase / std::f32::consts::PI).round() * std::f32::consts::PI;
+        phase + (rest - phase).clamp(-step, step)
+    };
+    phase % std::f32::consts::TAU
+}
+
+/// Oscillate agent cameras vertically with a frequency proportional to movement speed,
+/// settling back to no offset while agents stand still
+pub(crate) fn bob_agent_cameras(
+    time: Res<Time>,
+    settings: Res<GameSettings>,
+    actor_query: Query<&Velocity, With<Actor>>,
+    mut camera_query: Query<(&Parent, &mut Transform, &mut ViewBobPhase)>,
+) {
+    let Some(view_bob) = &settings.view_bob else {
+        return;
+    };
+    let full_speed = 10.0 * settings.cell_size;
+
+    for (parent, mut transform, mut phase) in camera_query.iter_mut() {
+        let Ok(velocity) = actor_query.get(parent.get()) else {
+            continue;
+        };
+        let speed = Vec2::new(velocity.linvel.x, velocity.linvel.z).length() / full_speed;
+
+        phase.0 = advance_view_bob_phase(phase.0, speed, view_bob, time.delta_seconds());
+        transform.translation.y = view_bob.amplitude * phase.0.sin();
+    }
+}
+
+pub(crate) fn regenerate_health(
+    time: Res<Time>,
+    settings: Res<GameSettings>,
+    mut actor_query: Query<(&mut Actor, &mut Regeneration)>,
+) {
+    let Some(regen) = &settings.health_regen else {
+        return;
+    };
+
+    for (mut actor, mut regeneration) in actor_query.iter_mut() {
+        // Taking damage restarts the grace period
+        if actor.health < regeneration.last_health {
+            regeneration.since_damage = 0.0;
+            regeneration.restored = 0.0;
+        }
+        regeneration.since_damage += time.delta_seconds();
+
+        actor.regenerating = actor.health > 0
+            && actor.health < settings.starting_health
+            && regeneration.since_damage >= regen.grace_period;
+
+        if actor.regenerating {
+            regeneration.restored += regen.rate * time.delta_seconds();
+            let restored = regeneration.restored.floor();
+            regeneration.restored -= restored;
+            actor.health = actor
+                .health
+                .saturating_add(restored as u16)
+                .min(settings.starting_health);
+        }
+
+        regeneration.last_health = actor.health;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn spawn_actors(seed: u64, settings: &GameSettings) -> Vec<(String, (f32, f32), f32)> {
+        let game_map = GameMap::default();
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        (0..16)
+            .map(|i| {
+                let actor_name = new_agent_name(&mut rng);
+                let spawn_pose = settings.spawn_plan.as_ref().and_then(|plan| plan.get(i));
+                let actor =
+                    new_agent_bundle(&game_map, actor_name, settings, spawn_pose, &mut rng).actor;
+                (actor.name, actor.position, actor.rotation)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spawn_is_deterministic_under_seed() {
+        let settings = GameSettings::default();
+        assert_eq!(spawn_actors(42, &settings), spawn_actors(42, &settings));
+        assert_ne!(spawn_actors(42, &settings), spawn_actors(43, &settings));
+    }
+
+    #[test]
+    fn spawn_uses_empty_space() {
+        let game_map = GameMap::default();
+        for (_, (x, z), _) in spawn_actors(7, &GameSettings::default()) {
+            assert!(game_map.empty_space.contains(&(x as usize, z as usize)));
+        }
+    }
+
+    #[test]
+    fn spawn_scales_with_cell_size() {
+        let settings = GameSettings {
+            cell_size: 2.5,
+            ..Default::default()
+        };
+        let scaled = spawn_actors(42, &settings);
+        let unscaled = spawn_actors(42, &GameSettings::default());
+
+        for ((_, (x, z), _), (_, (ux, uz), _)) in scaled.into_iter().zip(unscaled) {
+            assert_eq!((x, z), (ux * 2.5, uz * 2.5));
+        }
+    }
+
+    #[test]
+    fn spawn_avoids_exclusion_zones() {
+        let zone = SpawnExclusionZone {
+            x_min: 0,
+            z_min: 0,
+            x_max: 60,
+            z_max: 60,
+        };
+        let settings = GameSettings {
+            spawn_exclusion_zones: vec![zone.clone()],
+            ..Default::default()
+        };
+
+        for (_, (x, z), _) in spawn_actors(7, &settings) {
+            assert!(!zone.contains((x as usize, z as usize)));
+        }
+    }
+
+    #[test]
+    fn view_bob_settles_while_idle() {
+        let view_bob = ViewBob {
+            amplitude: 0.1,
+            frequency: 1.0,
+        };
+        let mut phase = 0.0;
+        for _ in 0..10 {
+            phase = advance_view_bob_phase(phase, 1.0, &view_bob, 0.03);
+        }
+        assert!(phase.sin() > 0.5);
+
+        for _ in 0..10 {
+            phase = advance_view_bob_phase(phase, 0.0, &view_bob, 0.03);
+        }
+        assert!(phase.sin().abs() < 1e-6);
+    }
+
+    #[test]
+    fn spawn_follows_spawn_plan() {
+        let settings = GameSettings {
+            spawn_plan: Some(vec![SpawnPose {
+                x: 12.0,
+                z: 18.0,
+                rotation: 1.5,
+            }]),
+            ..Default::default()
+        };
+        let actors = spawn_actors(42, &settings);
+
+        assert_eq!((actors[0].1, actors[0].2), ((12.0, 18.0), 1.5));
+        let game_map = GameMap::default();
+        for (_, (x, z), _) in &actors[1..] {
+            assert!(game_map.empty_space.contains(&(*x as usize, *z as usize)));
+        }
+    }
+}
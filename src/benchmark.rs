@@ -0,0 +1,199 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    app::AppExit,
+    prelude::*,
+    render::{renderer::render_system, RenderApp, RenderSet},
+};
+use bevy_rl::*;
+use rand::{prelude::SliceRandom, thread_rng};
+
+use crate::{actions::*, gym::EnvironmentState};
+
+const BENCHMARK_ACTIONS: [&str; 9] = [
+    "FORWARD",
+    "BACKWARD",
+    "LEFT",
+    "RIGHT",
+    "TURN_LEFT",
+    "TURN_RIGHT",
+    "SHOOT",
+    "STRAFE_LEFT",
+    "STRAFE_RIGHT",
+];
+
+/// Progress of `--mode benchmark` run
+#[derive(Resource)]
+pub(crate) struct Benchmark {
+    pub(crate) steps: u32,
+    pub(crate) completed: u32,
+    /// Resets after rounds that ended during the run
+    pub(crate) resets: u32,
+    pub(crate) started: Option<Instant>,
+}
+
+impl Benchmark {
+    pub(crate) fn new(steps: u32) -> Self {
+        Self {
+            steps,
+            completed: 0,
+            resets: 0,
+            started: None,
+        }
+    }
+}
+
+/// CPU time of rendered frames, written by the render app and read by `benchmark_control`
+#[derive(Resource, Clone, Default)]
+pub(crate) struct RenderTimings(Arc<Mutex<FrameTimes>>);
+
+#[derive(Default)]
+pub(crate) struct FrameTimes {
+    frames: u32,
+    /// Time spent in bevy's render graph
+    render: Duration,
+    /// Rest of the render stage, where bevy_rl copies agent views from GPU to RAM
+    readback: Duration,
+}
+
+/// Timestamps of the current frame in the render app
+#[derive(Resource, Default)]
+struct FrameMarks {
+    stage_started: Option<Instant>,
+    render_started: Option<Instant>,
+    render: Duration,
+}
+
+/// Time the render stage of every frame, split into the render graph and the rest of the
+/// stage, which is the copy of agent views to RAM
+pub(crate) fn add_render_timings(app: &mut App) {
+    let render_timings = RenderTimings::default();
+    app.insert_resource(render_timings.clone());
+
+    let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+        return;
+    };
+    render_app
+        .insert_resource(render_timings)
+        .init_resource::<FrameMarks>()
+        .add_system(
+            mark_stage_start
+                .after(RenderSet::PhaseSortFlush)
+                .before(RenderSet::Render),
+        )
+        .add_system(
+            mark_render_start
+                .in_set(RenderSet::Render)
+                .before(render_system),
+        )
+        .add_system(
+            mark_render_end
+                .in_set(RenderSet::Render)
+                .after(render_system),
+        )
+        .add_system(
+            record_frame_times
+                .after(RenderSet::Render)
+                .before(RenderSet::RenderFlush),
+        );
+}
+
+fn mark_stage_start(mut frame_marks: ResMut<FrameMarks>) {
+    frame_marks.stage_started = Some(Instant::now());
+}
+
+fn mark_render_start(mut frame_marks: ResMut<FrameMarks>) {
+    frame_marks.render_started = Some(Instant::now());
+}
+
+fn mark_render_end(mut frame_marks: ResMut<FrameMarks>) {
+    if let Some(render_started) = frame_marks.render_started.take() {
+        frame_marks.render = render_started.elapsed();
+    }
+}
+
+fn record_frame_times(mut frame_marks: ResMut<FrameMarks>, render_timings: Res<RenderTimings>) {
+    let Some(stage_started) = frame_marks.stage_started.take() else {
+        return;
+    };
+    let stage = stage_started.elapsed();
+    let render = std::mem::take(&mut frame_marks.render);
+
+    let mut frame_times = render_timings.0.lock().unwrap();
+    frame_times.frames += 1;
+    frame_times.render += render;
+    frame_times.readback += stage.saturating_sub(render);
+}
+
+/// Drive agents with random actions instead of waiting for REST API requests;
+/// reset the environment when a round ends
+pub(crate) fn benchmark_control(
+    mut benchmark: ResMut<Benchmark>,
+    mut pause_event_reader: EventReader<EventPause>,
+    mut control_event_writer: EventWriter<EventControl>,
+    mut reset_event_writer: EventWriter<EventReset>,
+    mut app_exit_writer: EventWriter<AppExit>,
+    render_timings: Res<RenderTimings>,
+    ai_gym_state: Res<AIGymState<Actions, EnvironmentState>>,
+) {
+    if pause_event_reader.iter().count() == 0 {
+        return;
+    }
+
+    if benchmark.started.is_none() {
+        // Frames rendered before the first step are not measured
+        *render_timings.0.lock().unwrap() = FrameTimes::default();
+    }
+    let started = *benchmark.started.get_or_insert_with(Instant::now);
+
+    if benchmark.completed == benchmark.steps {
+        let elapsed = started.elapsed().as_secs_f64();
+        println!("steps: {}", benchmark.completed);
+        println!("resets: {}", benchmark.resets);
+        println!("steps/sec: {:.2}", benchmark.completed as f64 / elapsed);
+        println!(
+            "mean step time: {:.2} ms",
+            1000.0 * elapsed / benchmark.completed as f64
+        );
+
+        let frame_times = render_timings.0.lock().unwrap();
+        if frame_times.frames > 0 {
+            let frames = frame_times.frames as f64;
+            println!("frames: {}", frame_times.frames);
+            println!(
+                "mean render time: {:.2} ms/frame",
+                1000.0 * frame_times.render.as_secs_f64() / frames
+            );
+            println!(
+                "mean readback time: {:.2} ms/frame",
+                1000.0 * frame_times.readback.as_secs_f64() / frames
+            );
+        }
+        app_exit_writer.send(AppExit);
+        return;
+    }
+
+    let ai_gym_state = ai_gym_state.lock().unwrap();
+    // Round over terminates every agent; start a new round instead of stepping dead agents
+    if ai_gym_state
+        .terminations
+        .iter()
+        .all(|terminated| *terminated)
+    {
+        reset_event_writer.send(EventReset);
+        benchmark.resets += 1;
+        return;
+    }
+
+    let num_agents = ai_gym_state.settings.num_agents;
+    let mut rng = thread_rng();
+    let actions = (0..num_agents)
+        .map(|_| BENCHMARK_ACTIONS.choose(&mut rng).map(|a| a.to_string()))
+        .collect();
+
+    control_event_writer.send(EventControl(actions));
+    benchmark.completed += 1;
+}
@@ -0,0 +1,210 @@
+//! Blocking client of the gym REST API served by bevy_rl, enabled with the `client` feature.
+//! It speaks plain HTTP/1.0 over `std::net::TcpStream` to avoid an HTTP client dependency.
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Result of `/step` for a single agent
+#[derive(Deserialize, Debug, Clone)]
+pub struct StepResult {
+    /// `None` when the reward is not finite, bevy_rl serializes NaN and infinity as `null`
+    pub reward: Option<f32>,
+    pub is_terminated: bool,
+}
+
+/// Actor as reported in `/state`
+#[derive(Deserialize, Debug, Clone)]
+pub struct ActorState {
+    pub position: (f32, f32),
+    pub rotation: f32,
+    pub name: String,
+    pub health: u16,
+    pub last_damager: Option<String>,
+}
+
+/// Subset of `EnvironmentState` returned by `/state`
+#[derive(Deserialize, Debug, Clone)]
+pub struct State {
+    pub step: u64,
+    pub actors: Vec<ActorState>,
+    /// `None` for rewards that are not finite
+    pub raw_rewards: Vec<Option<f32>>,
+}
+
+/// Routes of the gym REST API as `(method, path)`, all registered by bevy_rl as GET
+const ROUTE_RESET: (&str, &str) = ("GET", "/reset");
+const ROUTE_STEP: (&str, &str) = ("GET", "/step");
+const ROUTE_VISUAL_OBSERVATIONS: (&str, &str) = ("GET", "/visual_observations");
+const ROUTE_STATE: (&str, &str) = ("GET", "/state");
+
+#[derive(Serialize)]
+struct StepAction<'a> {
+    action: Option<&'a str>,
+}
+
+pub struct GymClient {
+    address: String,
+}
+
+impl Default for GymClient {
+    fn default() -> Self {
+        Self::new("127.0.0.1:7878")
+    }
+}
+
+impl GymClient {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+        }
+    }
+
+    pub fn reset(&self) -> Result<(), String> {
+        self.request(ROUTE_RESET, "").map(|_| ())
+    }
+
+    /// Send one action per agent, `None` for no action
+    pub fn step(&self, actions: &[Option<&str>]) -> Result<Vec<StepResult>, String> {
+        let actions: Vec<StepAction> = actions
+            .iter()
+            .map(|&action| StepAction { action })
+            .collect();
+        let payload = serde_json::to_string(&actions).map_err(|e| e.to_string())?;
+        let body = self.request(
+            ROUTE_STEP,
+            &format!("?payload={}", percent_encode(&payload)),
+        )?;
+        serde_json::from_slice(&body).map_err(|e| e.to_string())
+    }
+
+    /// PNG of all agent views side by side
+    pub fn screen(&self) -> Result<Vec<u8>, String> {
+        self.request(ROUTE_VISUAL_OBSERVATIONS, "")
+    }
+
+    pub fn state(&self) -> Result<State, String> {
+        let body = self.request(ROUTE_STATE, "")?;
+        serde_json::from_slice(&body).map_err(|e| e.to_string())
+    }
+
+    /// Send a request to `route` with `query` appended to its path and return the response
+    /// body of a `200 OK` response
+    fn request(&self, route: (&str, &str), query: &str) -> Result<Vec<u8>, String> {
+        let (method, path) = route;
+        let mut stream = TcpStream::connect(&self.address).map_err(|e| e.to_string())?;
+        write!(
+            stream,
+            "{method} {path}{query} HTTP/1.0\r\nHost: {}\r\nContent-Length: 0\r\n\r\n",
+            self.address
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut response = Vec::new();
+        stream
+            .read_to_end(&mut response)
+            .map_err(|e| e.to_string())?;
+        parse_response(&response)
+    }
+}
+
+/// Split an HTTP response into status and body
+fn parse_response(response: &[u8]) -> Result<Vec<u8>, String> {
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("malformed HTTP response")?;
+    let headers = String::from_utf8_lossy(&response[..header_end]);
+    let status_line = headers.lines().next().unwrap_or_default();
+
+    match status_line.split_whitespace().nth(1) {
+        Some("200") => Ok(response[header_end + 4..].to_vec()),
+        _ => Err(format!("request failed: {status_line}")),
+    }
+}
+
+/// Percent-encode everything except unreserved URL characters
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accept one connection on a local port, answer it with `response` and return the
+    /// request line the client sent
+    fn serve_once(response: &'static [u8]) -> (GymClient, std::thread::JoinHandle<String>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = GymClient::new(&listener.local_addr().unwrap().to_string());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0; 1];
+                stream.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            stream.write_all(response).unwrap();
+            let request = String::from_utf8(request).unwrap();
+            request.lines().next().unwrap().to_string()
+        });
+        (client, server)
+    }
+
+    #[test]
+    fn step_sends_get_with_payload() {
+        let (client, server) = serve_once(
+            b"HTTP/1.1 200 OK\r\n\r\n[{\"reward\":1.5,\"is_terminated\":false},{\"reward\":null,\"is_terminated\":true}]",
+        );
+        let results = client.step(&[Some("FORWARD"), None]).unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            "GET /step?payload=%5B%7B%22action%22%3A%22FORWARD%22%7D%2C%7B%22action%22%3Anull%7D%5D HTTP/1.0"
+        );
+        assert_eq!(results[0].reward, Some(1.5));
+        assert_eq!(results[1].reward, None);
+        assert!(results[1].is_terminated);
+    }
+
+    #[test]
+    fn state_accepts_null_rewards() {
+        let (client, server) = serve_once(
+            b"HTTP/1.1 200 OK\r\n\r\n{\"step\":3,\"actors\":[],\"raw_rewards\":[null,2.0]}",
+        );
+        let state = client.state().unwrap();
+
+        assert_eq!(server.join().unwrap(), "GET /state HTTP/1.0");
+        assert_eq!(state.step, 3);
+        assert_eq!(state.raw_rewards, vec![None, Some(2.0)]);
+    }
+
+    #[test]
+    fn percent_encode_escapes_reserved_characters() {
+        assert_eq!(
+            percent_encode(r#"[{"action":"FORWARD"}]"#),
+            "%5B%7B%22action%22%3A%22FORWARD%22%7D%5D"
+        );
+    }
+
+    #[test]
+    fn parse_response_returns_body_of_ok_response() {
+        assert_eq!(
+            parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]"),
+            Ok(b"[]".to_vec())
+        );
+        assert!(parse_response(b"HTTP/1.1 500 Internal Server Error\r\n\r\n").is_err());
+    }
+}
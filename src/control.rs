@@ -2,51 +2,59 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
-use crate::{actions::*, actors::*, events::*};
-
-#[derive(Resource)]
-pub(crate) struct DelayedControlTimer(pub(crate) Timer);
+use crate::{actions::*, actors::*, events::*, settings::GameSettings};
 
 pub(crate) fn control_agents(
     agent_actions: Vec<Option<Actions>>,
-    mut agent_movement_query: Query<(&mut Velocity, &mut Transform, &Actor)>,
-    mut collision_events: EventReader<CollisionEvent>,
+    speed: f32,
+    mut agent_movement_query: Query<(&mut Velocity, &mut Transform, &AgentIndex, &Actor)>,
     mut event_gun_shot: EventWriter<EventGunShot>,
 ) {
-    for (i, (mut velocity, transform, actor)) in agent_movement_query.iter_mut().enumerate() {
+    for (mut velocity, transform, index, actor) in agent_movement_query.iter_mut() {
         *velocity = Velocity { ..default() };
 
         if actor.health == 0 {
             continue;
         }
 
-        if agent_actions[i].is_some() {
-            let agent_actions = agent_actions[i].clone().unwrap();
-
+        if let Some(Some(agent_actions)) = agent_actions.get(index.0) {
             if agent_actions.contains(Actions::FORWARD) {
                 *velocity = Velocity {
-                    linvel: velocity.linvel + 10. * transform.forward().normalize(),
+                    linvel: velocity.linvel + speed * transform.forward().normalize(),
                     ..default()
                 }
             }
             if agent_actions.contains(Actions::BACKWARD) {
                 *velocity = Velocity {
-                    linvel: velocity.linvel + 10. * transform.left().normalize(),
+                    linvel: velocity.linvel + speed * transform.back().normalize(),
                     ..default()
                 }
             }
             if agent_actions.contains(Actions::LEFT) {
                 *velocity = Velocity {
-                    linvel: velocity.linvel + 10. * -transform.forward().normalize(),
+                    linvel: velocity.linvel + speed * transform.left().normalize(),
                     ..default()
                 }
             }
             if agent_actions.contains(Actions::RIGHT) {
                 *velocity = Velocity {
-                    linvel: velocity.linvel + 10. * transform.right().normalize(),
+                    linvel: velocity.linvel + speed * transform.right().normalize(),
                     ..default()
                 }
             }
+            // Strafes circle a point ahead: move sideways while turning the other way
+            if agent_actions.contains(Actions::STRAFE_LEFT) {
+                *velocity = Velocity {
+                    linvel: velocity.linvel + speed * transform.left().normalize(),
+                    angvel: Vec3::new(0.2, -0.5 * 3.14, 0.8),
+                }
+            }
+            if agent_actions.contains(Actions::STRAFE_RIGHT) {
+                *velocity = Velocity {
+                    linvel: velocity.linvel + speed * transform.right().normalize(),
+                    angvel: Vec3::new(0.2, 0.5 * 3.14, 0.8),
+                }
+            }
             if agent_actions.contains(Actions::TURN_LEFT) {
                 *velocity = Velocity {
                     linvel: velocity.linvel,
@@ -65,13 +73,205 @@ pub(crate) fn control_agents(
                 });
             }
         }
+    }
+}
+
+/// Stop agents that move into a wall or another agent; they can still turn and back away.
+/// With `GameSettings::wall_sliding` the physics solver only removes the motion into the wall.
+pub(crate) fn stop_on_contact(
+    settings: Res<GameSettings>,
+    rapier_context: Res<RapierContext>,
+    mut actor_query: Query<(Entity, &mut Velocity), With<Actor>>,
+) {
+    if settings.wall_sliding {
+        return;
+    }
+
+    for (entity, mut velocity) in actor_query.iter_mut() {
+        let blocked = rapier_context
+            .contacts_with(entity)
+            .filter(|pair| pair.has_any_active_contacts())
+            .any(|pair| {
+                pair.manifolds().any(|manifold| {
+                    // Contact normal pointing from the agent to the obstacle
+                    let normal = if pair.collider1() == entity {
+                        manifold.normal()
+                    } else {
+                        -manifold.normal()
+                    };
+                    // Resting on the floor is not a collision
+                    normal.y.abs() < 0.5 && velocity.linvel.dot(normal) >= 0.0
+                })
+            });
+
+        if blocked {
+            velocity.linvel = Vec3::ZERO;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{
+        render::{settings::WgpuSettings, RenderPlugin},
+        scene::ScenePlugin,
+        time::TimePlugin,
+    };
+
+    /// Run a headless physics app with a wall along the x axis and an agent moving forward
+    /// into it at 45°, return the agent position after `frames` updates
+    fn push_agent_into_wall(frames: usize, wall_sliding: bool) -> Vec3 {
+        let settings = GameSettings {
+            wall_sliding,
+            ..Default::default()
+        };
+
+        let mut app = App::new();
+        app.add_plugin(WindowPlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_plugin(ScenePlugin)
+            .add_plugin(RenderPlugin {
+                wgpu_settings: WgpuSettings {
+                    backends: None,
+                    ..default()
+                },
+            })
+            .add_plugin(ImagePlugin::default())
+            .add_plugin(TransformPlugin)
+            .add_plugin(TimePlugin)
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+            .add_event::<EventGunShot>()
+            .insert_resource(settings.clone())
+            .add_systems(
+                (
+                    |query: Query<(&mut Velocity, &mut Transform, &AgentIndex, &Actor)>,
+                     event_gun_shot: EventWriter<EventGunShot>| {
+                        let actions = vec![Some(Actions::FORWARD)];
+                        control_agents(actions, 5.0, query, event_gun_shot);
+                    },
+                    stop_on_contact,
+                )
+                    .chain(),
+            );
+
+        let mut rapier_configuration = app.world.resource_mut::<RapierConfiguration>();
+        rapier_configuration.gravity = Vec3::ZERO;
+        rapier_configuration.timestep_mode = TimestepMode::Fixed {
+            dt: 1.0 / 60.0,
+            substeps: 1,
+        };
+
+        app.world.spawn((
+            TransformBundle::default(),
+            RigidBody::Fixed,
+            Collider::cuboid(50.0, 1.0, 0.5),
+        ));
+
+        let agent = app
+            .world
+            .spawn((
+                TransformBundle::from(Transform {
+                    translation: Vec3::new(0.0, 0.0, 3.0),
+                    rotation: Quat::from_rotation_y(-std::f32::consts::FRAC_PI_4),
+                    ..default()
+                }),
+                RigidBody::Dynamic,
+                Collider::ball(0.5),
+                Friction::coefficient(settings.actor_friction),
+                LockedAxes::ROTATION_LOCKED,
+                Velocity::default(),
+                AgentIndex(0),
+                Actor {
+                    position: (0.0, 3.0),
+                    rotation: 0.0,
+                    name: "agent".to_string(),
+                    health: settings.starting_health,
+                    regenerating: false,
+                    shots: 0,
+                    hits: 0,
+                    last_damager: None,
+                    kills: 0,
+                },
+            ))
+            .id();
+
+        for _ in 0..frames {
+            app.update();
+        }
+
+        app.world
+            .entity(agent)
+            .get::<Transform>()
+            .unwrap()
+            .translation
+    }
+
+    /// Velocity an agent facing -z gets from `action`
+    fn action_velocity(action: Actions) -> Velocity {
+        let mut app = App::new();
+        app.add_event::<EventGunShot>().add_system(
+            move |query: Query<(&mut Velocity, &mut Transform, &AgentIndex, &Actor)>,
+                  event_gun_shot: EventWriter<EventGunShot>| {
+                control_agents(vec![Some(action.clone())], 1.0, query, event_gun_shot);
+            },
+        );
+        let agent = app
+            .world
+            .spawn((
+                Transform::default(),
+                Velocity::default(),
+                AgentIndex(0),
+                Actor {
+                    position: (0.0, 0.0),
+                    rotation: 0.0,
+                    name: "agent".to_string(),
+                    health: 100,
+                    regenerating: false,
+                    shots: 0,
+                    hits: 0,
+                    last_damager: None,
+                    kills: 0,
+                },
+            ))
+            .id();
+        app.update();
+
+        *app.world.entity(agent).get::<Velocity>().unwrap()
+    }
+
+    #[test]
+    fn movement_actions_are_distinct() {
+        assert_eq!(action_velocity(Actions::FORWARD).linvel, Vec3::NEG_Z);
+        assert_eq!(action_velocity(Actions::BACKWARD).linvel, Vec3::Z);
+        assert_eq!(action_velocity(Actions::LEFT).linvel, Vec3::NEG_X);
+        assert_eq!(action_velocity(Actions::RIGHT).linvel, Vec3::X);
+        assert_eq!(action_velocity(Actions::LEFT).angvel, Vec3::ZERO);
+
+        let strafe_left = action_velocity(Actions::STRAFE_LEFT);
+        assert_eq!(strafe_left.linvel, Vec3::NEG_X);
+        assert!(strafe_left.angvel.y < 0.0);
+        let strafe_right = action_velocity(Actions::STRAFE_RIGHT);
+        assert_eq!(strafe_right.linvel, Vec3::X);
+        assert!(strafe_right.angvel.y > 0.0);
+    }
+
+    #[test]
+    fn wall_sliding_keeps_motion_along_wall() {
+        let position = push_agent_into_wall(120, true);
+
+        // Stopped by the wall...
+        assert!(position.z > 0.9 && position.z < 1.1, "{position:?}");
+        // ...while sliding sideways along it
+        assert!(position.x > 3.0, "{position:?}");
+    }
+
+    #[test]
+    fn agent_stops_at_wall_without_wall_sliding() {
+        let position = push_agent_into_wall(120, false);
 
-        collision_events.iter().for_each(|_| {
-            // Stop the motion upon collision
-            *velocity = Velocity {
-                linvel: Vec3::ZERO,
-                angvel: Vec3::ZERO,
-            };
-        });
+        // Reaches the wall at x = 2 and stays there
+        assert!(position.z > 0.9 && position.z < 1.1, "{position:?}");
+        assert!(position.x < 2.5, "{position:?}");
     }
 }
@@ -1,9 +1,16 @@
 use bevy::prelude::*;
 use bevy_mod_raycast::RaycastSource;
 use bevy_rapier3d::prelude::*;
-use bevy_rl::state::AIGymState;
+use rand::Rng;
 
-use crate::{actions::*, actors::Actor, game::*, gym::EnvironmentState, level::*};
+use crate::{
+    actors::{Actor, AgentIndex},
+    game::*,
+    gym::AgentRewards,
+    level::*,
+    rewards::RewardFunctions,
+    settings::GameSettings,
+};
 
 #[derive(Debug)]
 pub(crate) struct EventGunShot {
@@ -14,20 +21,44 @@ pub(crate) struct EventGunShot {
 pub(crate) struct EventDamage {
     pub(crate) from: String,
     pub(crate) to: String,
+    pub(crate) amount: u16,
 }
 
 #[derive(Debug)]
 pub(crate) struct EventRoundOver;
 
+#[derive(Debug)]
+pub(crate) struct EventWarp {
+    pub(crate) to: String,
+    pub(crate) x: f32,
+    pub(crate) z: f32,
+    pub(crate) rotation: f32,
+}
+
+/// Damage of a hit at point-blank range
+const SHOT_DAMAGE: u16 = 100;
+
+/// Scale of hit probability and damage of a shot at `distance`, see
+/// `GameSettings::shot_falloff_distance`
+fn shot_falloff(distance: f32, falloff_distance: Option<f32>) -> f32 {
+    match falloff_distance {
+        Some(falloff_distance) => falloff_distance / (falloff_distance + distance),
+        None => 1.0,
+    }
+}
+
 // ------
 // Events
 // ------
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn event_gun_shot(
     mut commands: Commands,
     shooting_query: Query<(&Parent, &RaycastSource<RaycastMarker>)>,
     actor_query: Query<(Entity, &Children, &Actor)>,
     wall_query: Query<(Entity, &Wall)>,
+    mut env_rng: ResMut<EnvRng>,
+    settings: Res<GameSettings>,
 
     mut gunshot_event: EventReader<EventGunShot>,
     mut event_damage: EventWriter<EventDamage>,
@@ -49,7 +80,9 @@ pub(crate) fn event_gun_shot(
             continue;
         }
 
-        let hit_entity = r.unwrap().0;
+        let (hit_entity, intersection) = r.unwrap();
+        let hit_entity = *hit_entity;
+        let falloff = shot_falloff(intersection.distance(), settings.shot_falloff_distance);
 
         let mut player_hit = false;
         for (_, children, enemy) in actor_query.iter() {
@@ -58,12 +91,18 @@ pub(crate) fn event_gun_shot(
                 continue;
             }
 
+            player_hit = true;
+
+            let hit_probability = settings.shot_accuracy * falloff;
+            if hit_probability < 1.0 && env_rng.0.gen::<f32>() >= hit_probability {
+                continue;
+            }
+
             event_damage.send(EventDamage {
                 from: gunshot_event.from.clone(),
                 to: enemy.name.clone(),
+                amount: (SHOT_DAMAGE as f32 * falloff).ceil() as u16,
             });
-
-            player_hit = true;
             continue;
         }
 
@@ -80,32 +119,100 @@ pub(crate) fn event_gun_shot(
 }
 
 pub(crate) fn event_damage(
-    mut commands: Commands,
-    mut player_query: Query<(Entity, &Children, &mut Actor, &mut Velocity)>,
+    mut player_query: Query<(&AgentIndex, &mut Actor)>,
     mut event_damage: EventReader<EventDamage>,
-    ai_gym_state: ResMut<AIGymState<Actions, EnvironmentState>>,
+    mut agent_rewards: ResMut<AgentRewards>,
+    reward_functions: Res<RewardFunctions>,
+    settings: Res<GameSettings>,
 ) {
     for damage_event in event_damage.iter() {
         if damage_event.from == damage_event.to {
             continue;
         }
 
-        let mut ai_gym_state = ai_gym_state.lock().unwrap();
+        let victim = match player_query
+            .iter_mut()
+            .find(|(_, actor)| actor.health > 0 && actor.name == damage_event.to)
+        {
+            Some((_, mut actor)) => {
+                actor.health = actor.health.saturating_sub(damage_event.amount);
+                actor.last_damager = Some(damage_event.from.clone());
+                (actor.health == 0).then(|| actor.clone())
+            }
+            None => None,
+        };
+
+        // Reward the shooter that dealt the final blow
+        if let Some(victim) = victim {
+            if let Some((index, mut shooter)) = player_query
+                .iter_mut()
+                .find(|(_, actor)| actor.name == damage_event.from)
+            {
+                shooter.kills += 1;
+                agent_rewards.0[index.0] += reward_functions.0.kill(&settings, &shooter, &victim);
+            }
+        }
+    }
+}
 
-        if let Some((i, (entity, _, mut actor, mut _velocity))) = player_query
+pub(crate) fn event_warp(
+    mut actor_query: Query<(&mut Transform, &mut Velocity, &mut Actor)>,
+    mut event_warp: EventReader<EventWarp>,
+) {
+    for warp_event in event_warp.iter() {
+        if let Some((mut transform, mut velocity, mut actor)) = actor_query
             .iter_mut()
-            .filter(|(_, _, actor, _)| actor.health > 0)
-            .enumerate()
-            .find(|(_, p)| p.2.name == damage_event.to)
+            .find(|(_, _, actor)| actor.name == warp_event.to)
         {
-            actor.health -= 100;
+            transform.translation = Vec3::new(warp_event.x, transform.translation.y, warp_event.z);
+            transform.rotation = Quat::from_rotation_y(warp_event.rotation);
+            *velocity = Velocity { ..default() };
+
+            actor.position = (warp_event.x, warp_event.z);
+            actor.rotation = warp_event.rotation;
+        }
+    }
+}
+
+/// Count shots and hits of every agent and reward shots with `RewardFunction::shot`
+pub(crate) fn event_shot_accuracy(
+    mut actor_query: Query<(&AgentIndex, &mut Actor)>,
+    mut gunshot_event: EventReader<EventGunShot>,
+    mut event_damage: EventReader<EventDamage>,
+    mut agent_rewards: ResMut<AgentRewards>,
+    reward_functions: Res<RewardFunctions>,
+    settings: Res<GameSettings>,
+) {
+    let shooters_hit: Vec<String> = event_damage
+        .iter()
+        .filter(|damage_event| damage_event.from != damage_event.to)
+        .map(|damage_event| damage_event.from.clone())
+        .collect();
 
-            commands
-                .entity(entity)
-                .insert(Velocity { ..default() })
-                .insert(Visibility::Hidden);
+    for gunshot_event in gunshot_event.iter() {
+        let hit = shooters_hit.contains(&gunshot_event.from);
 
-            ai_gym_state.set_reward(i, 10.0);
+        if let Some((index, mut actor)) = actor_query
+            .iter_mut()
+            .find(|(_, actor)| actor.name == gunshot_event.from)
+        {
+            actor.shots += 1;
+            if hit {
+                actor.hits += 1;
+            }
+            agent_rewards.0[index.0] += reward_functions.0.shot(&settings, &actor, hit);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shot_falloff_halves_at_falloff_distance() {
+        assert_eq!(shot_falloff(0.0, Some(20.0)), 1.0);
+        assert_eq!(shot_falloff(20.0, Some(20.0)), 0.5);
+        assert_eq!(shot_falloff(1000.0, None), 1.0);
+    }
+}
@@ -1,9 +1,13 @@
-use bevy::prelude::*;
-use bevy_mod_raycast::{DefaultPluginState, DefaultRaycastingPlugin};
+use bevy::{prelude::*, render::camera::ScalingMode};
+use bevy_mod_raycast::{DefaultPluginState, DefaultRaycastingPlugin, RaycastSource};
 use bevy_rapier3d::prelude::*;
 use bevy_rl::*;
+use rand::{rngs::StdRng, SeedableRng};
 
-use crate::{actions::*, actors::*, events::*, gym::*, level::*};
+use crate::{
+    actions::*, actors::*, benchmark::*, control::stop_on_contact, events::*, gym::*, level::*,
+    replay::*, rewards::*, settings::*,
+};
 
 // ----------
 // Components
@@ -15,6 +19,19 @@ pub(crate) struct RoundTimer(pub(crate) Timer);
 #[derive(Clone, Reflect)]
 pub(crate) struct RaycastMarker;
 
+/// Random number generator of the environment, seeded with `GameSettings::seed`
+#[derive(Resource)]
+pub(crate) struct EnvRng(pub(crate) StdRng);
+
+impl EnvRng {
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => Self(StdRng::seed_from_u64(seed)),
+            None => Self(StdRng::from_entropy()),
+        }
+    }
+}
+
 // -------
 // Systems
 // -------
@@ -27,40 +44,208 @@ fn restart_round_timer(
     simulation_state.set(SimulationState::Running);
 }
 
-fn check_termination(
-    player_query: Query<&Actor>,
+/// Apply `GameSettings::physics_substeps` to the rapier timestep
+fn configure_physics(
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    settings: Res<GameSettings>,
+) {
+    rapier_configuration.timestep_mode = TimestepMode::Variable {
+        max_dt: 1.0 / 60.0,
+        time_scale: 1.0,
+        substeps: settings.physics_substeps,
+    };
+}
+
+/// Scale the tiled preview of agent views rendered by bevy_rl to fit the window
+fn fit_preview_to_window(
+    mut projection_query: Query<&mut OrthographicProjection, Added<Camera2d>>,
+    ai_gym_state: Res<AIGymState<Actions, EnvironmentState>>,
+) {
+    let ai_gym_settings = ai_gym_state.lock().unwrap().settings.clone();
+    let number_of_columns = (ai_gym_settings.num_agents as f32).sqrt().ceil();
+    let number_of_rows = (ai_gym_settings.num_agents as f32 / number_of_columns).ceil();
+
+    for mut projection in projection_query.iter_mut() {
+        projection.scaling_mode = ScalingMode::AutoMin {
+            min_width: number_of_columns * ai_gym_settings.width as f32,
+            min_height: number_of_rows * ai_gym_settings.height as f32,
+        };
+    }
+}
+
+fn handle_actor_death(
+    mut commands: Commands,
+    actor_query: Query<(Entity, &AgentIndex, &Actor, Option<&Dead>)>,
+    mut agent_rewards: ResMut<AgentRewards>,
+    reward_functions: Res<RewardFunctions>,
+    settings: Res<GameSettings>,
+) {
+    for (entity, index, actor, dead) in actor_query.iter() {
+        if actor.health > 0 || dead.is_some() {
+            continue;
+        }
+
+        commands
+            .entity(entity)
+            .insert(Dead)
+            .insert(Velocity { ..default() })
+            .insert(Visibility::Hidden);
+
+        agent_rewards.0[index.0] += reward_functions.0.death(&settings, actor);
+    }
+}
+
+/// Reward living agents for every frame they touch another living agent
+fn reward_bumps(
+    time: Res<Time>,
+    rapier_context: Res<RapierContext>,
+    actor_query: Query<(Entity, &AgentIndex, &Actor)>,
+    mut agent_rewards: ResMut<AgentRewards>,
+    reward_functions: Res<RewardFunctions>,
+    settings: Res<GameSettings>,
+) {
+    let actors: Vec<(Entity, &AgentIndex, &Actor)> = actor_query.iter().collect();
+
+    for (entity, index, actor) in actors.iter() {
+        if actor.health == 0 {
+            continue;
+        }
+
+        for (other_entity, _, other) in actors.iter() {
+            let touching = other_entity != entity
+                && other.health > 0
+                && rapier_context
+                    .contact_pair(*entity, *other_entity)
+                    .is_some_and(|pair| pair.has_any_active_contacts());
+            if touching {
+                agent_rewards.0[index.0] +=
+                    reward_functions
+                        .0
+                        .bump(&settings, actor, other, time.delta_seconds());
+            }
+        }
+    }
+}
+
+/// Reward living agents for every frame their line of sight is on a living target,
+/// see `GameSettings::tracking_target`
+fn reward_tracking(
+    time: Res<Time>,
+    sight_query: Query<(&Parent, &RaycastSource<RaycastMarker>)>,
+    actor_query: Query<(Entity, &Children, &Actor)>,
+    index_query: Query<&AgentIndex>,
+    mut agent_rewards: ResMut<AgentRewards>,
+    reward_functions: Res<RewardFunctions>,
+    settings: Res<GameSettings>,
+) {
+    let actors: Vec<(Entity, &Children, &Actor)> = actor_query.iter().collect();
+
+    for (parent, raycast_source) in sight_query.iter() {
+        let Ok(index) = index_query.get(parent.get()) else {
+            continue;
+        };
+        let Some((_, _, actor)) = actors.iter().find(|(e, _, _)| *e == parent.get()) else {
+            continue;
+        };
+        if actor.health == 0 {
+            continue;
+        }
+        let Some((hit_entity, _)) = raycast_source.intersections().first() else {
+            continue;
+        };
+        let Some((_, _, target)) = actors.iter().find(|(e, children, other)| {
+            *e != parent.get() && other.health > 0 && children.contains(hit_entity)
+        }) else {
+            continue;
+        };
+
+        let on_target = match settings.tracking_target {
+            TrackingTarget::Any => true,
+            TrackingTarget::Nearest => nearest_enemy(actor, actors.iter().map(|(_, _, a)| *a))
+                .is_some_and(|nearest| nearest.name == target.name),
+        };
+        if on_target {
+            agent_rewards.0[index.0] +=
+                reward_functions
+                    .0
+                    .tracking(&settings, actor, target, time.delta_seconds());
+        }
+    }
+}
+
+pub(crate) fn check_termination(
+    player_query: Query<(&AgentIndex, &Actor)>,
     time: Res<Time>,
     // mut app_state: ResMut<State<AppState>>,
     mut round_timer: ResMut<RoundTimer>,
     ai_gym_state: ResMut<AIGymState<Actions, EnvironmentState>>,
     mut event_round_over_writer: EventWriter<EventRoundOver>,
+    settings: Res<GameSettings>,
 ) {
-    let zero_health_actors = player_query.iter().filter(|p| p.health == 0).count() as u32;
+    let zero_health_actors = player_query.iter().filter(|(_, p)| p.health == 0).count() as u32;
     round_timer.0.tick(time.delta());
     let seconds_left = round_timer.0.duration().as_secs() - round_timer.0.elapsed().as_secs();
 
     let mut ai_gym_state = ai_gym_state.lock().unwrap();
     let ai_gym_settings = ai_gym_state.settings.clone();
-    let agents: Vec<&Actor> = player_query.iter().collect();
-    #[allow(clippy::needless_range_loop)]
-    for i in 0..agents.len() {
-        if agents[i].health == 0 {
-            ai_gym_state.set_terminated(i, true);
+    for (index, agent) in player_query.iter() {
+        if agent.health == 0 {
+            ai_gym_state.set_terminated(index.0, true);
         }
     }
 
-    if ai_gym_settings.num_agents == zero_health_actors || seconds_left == 0 {
+    let succeeded = player_query
+        .iter()
+        .any(|(_, agent)| settings.is_success(agent.kills));
+
+    if ai_gym_settings.num_agents == zero_health_actors || seconds_left == 0 || succeeded {
         event_round_over_writer.send(EventRoundOver);
     }
 }
 
-pub(crate) fn build_game_app(_mode: String) -> App {
-    let gym_settings = AIGymSettings {
+/// Settings of the bevy_rl gym: observation size, number of agents and step length
+pub fn gym_settings() -> AIGymSettings {
+    AIGymSettings {
         width: 256,
         height: 256,
         num_agents: 16,
         pause_interval: 0.1,
         render_to_buffer: true,
+    }
+}
+
+/// Build the environment app; `replay` drives agents from an action log in `--mode replay`.
+/// Fails if `settings` do not pass `GameSettings::validate`.
+pub fn build_game_app(
+    mode: String,
+    settings: GameSettings,
+    replay: Option<ActionLog>,
+) -> Result<App, String> {
+    settings.validate()?;
+
+    if let Some(log) = &replay {
+        if log.seed != settings.seed {
+            eprintln!(
+                "Warning: action log was recorded with seed {:?}, settings use {:?}; replay may diverge",
+                log.seed, settings.seed
+            );
+        }
+    }
+
+    let gym_settings = gym_settings();
+
+    let num_agents = gym_settings.num_agents as usize;
+    let benchmark_steps = settings.benchmark_steps;
+    let window = match settings.window_resolution {
+        Some((width, height)) => Window {
+            resolution: (width, height).into(),
+            ..default()
+        },
+        None => Window::default(),
+    };
+    let image_plugin = match settings.preview_filter {
+        PreviewFilter::Nearest => ImagePlugin::default_nearest(),
+        PreviewFilter::Linear => ImagePlugin::default_linear(),
     };
 
     let mut app = App::new();
@@ -70,24 +255,52 @@ pub(crate) fn build_game_app(_mode: String) -> App {
         .insert_resource(DefaultPluginState::<RaycastMarker>::default())
         .insert_resource(AIGymState::<Actions, EnvironmentState>::new(gym_settings))
         .insert_resource(RoundTimer(Timer::from_seconds(60.0, TimerMode::Repeating)))
+        .insert_resource(AgentRewards(vec![0.0; num_agents]))
+        .init_resource::<RewardHistory>()
+        .init_resource::<StepCounter>()
+        .init_resource::<RewardNormalizer>()
+        .init_resource::<RewardFunctions>()
+        .insert_resource(LastActions(vec![None; num_agents]))
+        .insert_resource(AmbientLight {
+            color: Color::rgb(
+                settings.ambient_color[0],
+                settings.ambient_color[1],
+                settings.ambient_color[2],
+            ),
+            brightness: settings.ambient_brightness,
+        })
+        .insert_resource(EnvRng::new(settings.seed))
+        .insert_resource(settings)
         .init_resource::<GameMap>();
 
     // Events
     app.add_event::<EventGunShot>()
         .add_event::<EventDamage>()
+        .add_event::<EventWarp>()
         .add_event::<EventRoundOver>();
 
     // Plugins
-    app.add_plugins(DefaultPlugins)
-        // .add_plugin(WorldInspectorPlugin::new())
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_plugin(DefaultRaycastingPlugin::<RaycastMarker>::default())
-        // bevy_rl initialization
-        .add_plugin(AIGymPlugin::<Actions, EnvironmentState>::default());
+    app.add_plugins(
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: Some(window),
+                ..default()
+            })
+            .set(image_plugin),
+    )
+    // .add_plugin(WorldInspectorPlugin::new())
+    .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+    .add_plugin(DefaultRaycastingPlugin::<RaycastMarker>::default())
+    // bevy_rl initialization
+    .add_plugin(AIGymPlugin::<Actions, EnvironmentState>::default());
 
     // Game world logic
     app.add_state::<SimulationState>();
 
+    app.add_startup_system(configure_physics);
+    app.add_system(fit_preview_to_window);
+    app.add_system(save_snapshot);
+
     app.add_systems(
         (spawn_game_world, spawn_computer_actors, restart_round_timer)
             .chain()
@@ -98,8 +311,21 @@ pub(crate) fn build_game_app(_mode: String) -> App {
         (
             event_gun_shot,
             event_damage,
+            event_shot_accuracy
+                .after(event_gun_shot)
+                .before(collect_rewards),
+            handle_actor_death.after(event_damage),
+            reward_bumps.before(collect_rewards),
+            reward_tracking.before(collect_rewards),
+            regenerate_health.after(event_damage),
+            stop_on_contact,
+            sync_actor_transforms,
+            bob_agent_cameras,
+            move_walls,
+            event_warp,
             event_round_over,
             check_termination,
+            collect_rewards.after(handle_actor_death),
         )
             .in_set(OnUpdate(SimulationState::Running)),
     );
@@ -114,5 +340,24 @@ pub(crate) fn build_game_app(_mode: String) -> App {
             .in_set(OnUpdate(SimulationState::PausedForControl)),
     );
 
-    app
+    if mode == "benchmark" {
+        app.insert_resource(Benchmark::new(benchmark_steps));
+        add_render_timings(&mut app);
+        app.add_system(
+            benchmark_control
+                .after(bevy_rl_pause_request)
+                .in_set(OnUpdate(SimulationState::PausedForControl)),
+        );
+    }
+
+    if let Some(log) = replay {
+        app.insert_resource(Replay { log, completed: 0 });
+        app.add_system(
+            replay_control
+                .after(bevy_rl_pause_request)
+                .in_set(OnUpdate(SimulationState::PausedForControl)),
+        );
+    }
+
+    Ok(app)
 }
@@ -1,24 +1,152 @@
+use base64::Engine;
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 use bevy_rl::*;
+use rand::Rng;
 
 use serde::Serialize;
+use std::{collections::VecDeque, io::Cursor};
 
-use crate::{actions::*, actors::*, control::*, events::*, level::*};
+use crate::{
+    actions::*, actors::*, control::*, events::*, game::*, level::*, rewards::*, settings::*,
+};
 
 #[derive(Default, Serialize, Clone)]
-pub(crate) struct EnvironmentState {
+pub struct EnvironmentState {
+    /// Steps completed since the last reset
+    pub(crate) step: u64,
     pub(crate) map: GameMap,
     pub(crate) actors: Vec<Actor>,
+    /// Rewards before clipping
+    pub(crate) raw_rewards: Vec<f32>,
+    /// Agents that reached `GameSettings::success_kills`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) success: Vec<bool>,
+    /// Base64 JPEG previews of agent views, see `GameSettings::state_thumbnails`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) thumbnails: Vec<String>,
+    /// Last `GameSettings::reward_history_len` steps of the current episode
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) reward_history: Vec<RewardHistoryEntry>,
 }
 
+#[derive(Serialize, Clone)]
+pub(crate) struct RewardHistoryEntry {
+    pub(crate) step: u64,
+    pub(crate) rewards: Vec<f32>,
+}
+
+/// Running mean and variance of rewards (Welford's algorithm).
+/// Kept for the whole run, it is not cleared on reset.
+#[derive(Resource, Default)]
+pub(crate) struct RewardNormalizer {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl RewardNormalizer {
+    pub(crate) fn update(&mut self, reward: f32) {
+        self.count += 1;
+        let delta = reward as f64 - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (reward as f64 - self.mean);
+    }
+
+    pub(crate) fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 1.0;
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    pub(crate) fn normalize(&self, reward: f32) -> f32 {
+        ((reward as f64 - self.mean) / (self.variance().sqrt() + 1e-8)) as f32
+    }
+}
+
+/// Actions applied on the previous step, repeated by sticky actions
+#[derive(Resource, Default)]
+pub(crate) struct LastActions(pub(crate) Vec<Option<Actions>>);
+
+/// Steps completed since the last reset, reported as `EnvironmentState::step`
+#[derive(Resource, Default)]
+pub(crate) struct StepCounter(pub(crate) u64);
+
+/// Rewards returned by recent steps of the current episode
+#[derive(Resource, Default)]
+pub(crate) struct RewardHistory {
+    pub(crate) entries: VecDeque<RewardHistoryEntry>,
+}
+
+const THUMBNAIL_SIZE: u32 = 64;
+
+/// Encode a downscaled JPEG copy of an agent's view as base64
+fn encode_thumbnail(observation: &image::RgbaImage, filter: ObsFilter) -> String {
+    let thumbnail =
+        image::imageops::resize(observation, THUMBNAIL_SIZE, THUMBNAIL_SIZE, filter.into());
+    let thumbnail = image::DynamicImage::ImageRgba8(thumbnail).to_rgb8();
+
+    let mut bytes: Vec<u8> = Vec::new();
+    thumbnail
+        .write_to(
+            &mut Cursor::new(&mut bytes),
+            image::ImageOutputFormat::Jpeg(80),
+        )
+        .unwrap();
+
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+/// Trigger file of `save_snapshot`, removed once the snapshot is taken
+const SNAPSHOT_TRIGGER: &str = "SNAPSHOT";
+
+/// Save current agent views as PNG files to `GameSettings::snapshot_dir` when a
+/// `SNAPSHOT` file appears in it, or when F12 is pressed in the preview window
+pub(crate) fn save_snapshot(
+    keys: Res<Input<KeyCode>>,
+    ai_gym_state: Res<AIGymState<Actions, EnvironmentState>>,
+    settings: Res<GameSettings>,
+) {
+    let Some(snapshot_dir) = &settings.snapshot_dir else {
+        return;
+    };
+    let trigger = std::path::Path::new(snapshot_dir).join(SNAPSHOT_TRIGGER);
+    let triggered = std::fs::remove_file(&trigger).is_ok();
+    if !triggered && !keys.just_pressed(KeyCode::F12) {
+        return;
+    }
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let ai_gym_state = ai_gym_state.lock().unwrap();
+    for (i, observation) in ai_gym_state.visual_observations.iter().enumerate() {
+        let path = std::path::Path::new(snapshot_dir).join(format!("{timestamp}-{i}.png"));
+        if let Err(error) = observation.save(&path) {
+            eprintln!("Failed to save snapshot {}: {error}", path.display());
+        }
+    }
+}
+
+/// Per-agent rewards of the current step before they are passed to bevy_rl
+#[derive(Resource, Default, Clone)]
+pub(crate) struct AgentRewards(pub(crate) Vec<f32>);
+
 /// Handle bevy_rl::EventPauseResume
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn bevy_rl_pause_request(
     mut pause_event_reader: EventReader<EventPause>,
     ai_gym_state: Res<AIGymState<Actions, EnvironmentState>>,
     mut rapier_configuration: ResMut<RapierConfiguration>,
     game_map: Res<GameMap>,
-    query_actors: Query<(Entity, &Actor)>,
+    agent_rewards: Res<AgentRewards>,
+    mut reward_history: ResMut<RewardHistory>,
+    step_counter: Res<StepCounter>,
+    mut reward_normalizer: ResMut<RewardNormalizer>,
+    settings: Res<GameSettings>,
+    query_actors: Query<(&AgentIndex, &Actor)>,
 ) {
     if pause_event_reader.iter().count() == 0 {
         return;
@@ -27,36 +155,123 @@ pub(crate) fn bevy_rl_pause_request(
     let _ = pause_event_reader.iter().last();
     // Pause simulation (physics engine)
     rapier_configuration.physics_pipeline_active = false;
-    // Collect state into serializable struct
+
+    let mut ai_gym_state = ai_gym_state.lock().unwrap();
+    let thumbnails = if settings.state_thumbnails {
+        ai_gym_state
+            .visual_observations
+            .iter()
+            .map(|observation| encode_thumbnail(observation, settings.obs_filter))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Normalization statistics include rewards of the step that just finished
+    if settings.normalize_rewards {
+        agent_rewards
+            .0
+            .iter()
+            .for_each(|r| reward_normalizer.update(*r));
+    }
+
+    // Record rewards of the step that just finished, once per step;
+    // no step precedes the first pause after startup or reset
+    let recorded = reward_history.entries.back().map(|entry| entry.step);
+    if settings.reward_history_len > 0 && step_counter.0 > 0 && recorded != Some(step_counter.0) {
+        let entry = RewardHistoryEntry {
+            step: step_counter.0,
+            rewards: ai_gym_state.rewards.clone(),
+        };
+        reward_history.entries.push_back(entry);
+        while reward_history.entries.len() > settings.reward_history_len {
+            reward_history.entries.pop_front();
+        }
+    }
+
+    // Collect state into serializable struct, actors in agent index order
+    let mut actors: Vec<(&AgentIndex, &Actor)> = query_actors.iter().collect();
+    actors.sort_by_key(|(index, _)| index.0);
+    let actors: Vec<Actor> = actors.into_iter().map(|(_, a)| a.clone()).collect();
+    let success = if settings.success_kills.is_some() {
+        actors
+            .iter()
+            .map(|a| settings.is_success(a.kills))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
     let env_state = EnvironmentState {
+        step: step_counter.0,
         map: game_map.clone(),
-        actors: query_actors.iter().map(|(_, a)| a.clone()).collect(),
+        actors,
+        success,
+        raw_rewards: agent_rewards.0.clone(),
+        thumbnails,
+        reward_history: reward_history.entries.iter().cloned().collect(),
     };
     // Set bevy_rl gym state
-    let mut ai_gym_state = ai_gym_state.lock().unwrap();
     ai_gym_state.set_env_state(env_state);
 }
 
 /// Handle bevy_rl::EventControl
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn bevy_rl_control_request(
     ai_gym_state: Res<AIGymState<Actions, EnvironmentState>>,
     mut control_event_reader: EventReader<EventControl>,
     mut simulation_state: ResMut<NextState<SimulationState>>,
     mut rapier_configuration: ResMut<RapierConfiguration>,
-    query_actors: Query<(&mut Velocity, &mut Transform, &Actor)>,
-    collision_events: EventReader<CollisionEvent>,
+    query_actors: Query<(&mut Velocity, &mut Transform, &AgentIndex, &Actor)>,
     event_gun_shot: EventWriter<EventGunShot>,
+    mut event_warp: EventWriter<EventWarp>,
+    mut agent_rewards: ResMut<AgentRewards>,
+    mut last_actions: ResMut<LastActions>,
+    mut step_counter: ResMut<StepCounter>,
+    mut env_rng: ResMut<EnvRng>,
+    reward_functions: Res<RewardFunctions>,
+    settings: Res<GameSettings>,
 ) {
     if let Some(control) = control_event_reader.iter().next() {
-        let mut ai_gym_state = ai_gym_state.lock().unwrap();
+        step_counter.0 += 1;
+
+        let ai_gym_state = ai_gym_state.lock().unwrap();
         let ai_gym_settings = ai_gym_state.settings.clone();
         let unparsed_actions = &control.0;
         let mut actions: Vec<Option<Actions>> =
             (0..ai_gym_settings.num_agents).map(|_| None).collect();
+        let mut actor_names: Vec<Option<String>> = vec![None; actions.len()];
+        let mut step_rewards: Vec<f32> = vec![0.0; actions.len()];
+        for (_, _, index, actor) in query_actors.iter() {
+            if index.0 >= actions.len() {
+                continue;
+            }
+            let actors = query_actors.iter().map(|(_, _, _, actor)| actor);
+            let nearest = nearest_enemy(actor, actors);
+            actor_names[index.0] = Some(actor.name.clone());
+            step_rewards[index.0] = reward_functions.0.step(&settings, actor, nearest);
+        }
 
-        for i in 0..unparsed_actions.len() {
+        // Actions beyond the number of agents are ignored
+        for i in 0..unparsed_actions.len().min(actions.len()) {
             if let Some(unparsed_action) = unparsed_actions[i].clone() {
-                ai_gym_state.set_reward(i, 0.0);
+                agent_rewards.0[i] = step_rewards[i];
+
+                // Teleport requests are handled separately from movement actions
+                if unparsed_action.starts_with("WARP") {
+                    let warp = parse_warp(&unparsed_action).filter(|_| settings.allow_warp);
+                    if let (Some((x, z, rotation)), Some(Some(name))) = (warp, actor_names.get(i)) {
+                        event_warp.send(EventWarp {
+                            to: name.clone(),
+                            x,
+                            z,
+                            rotation,
+                        });
+                    }
+                    actions[i] = None;
+                    continue;
+                }
+
                 // Pass control inputs to your agents
 
                 let action = match unparsed_action.as_str() {
@@ -67,6 +282,8 @@ pub(crate) fn bevy_rl_control_request(
                     "TURN_LEFT" => Some(Actions::TURN_LEFT),
                     "TURN_RIGHT" => Some(Actions::TURN_RIGHT),
                     "SHOOT" => Some(Actions::SHOOT),
+                    "STRAFE_LEFT" => Some(Actions::STRAFE_LEFT),
+                    "STRAFE_RIGHT" => Some(Actions::STRAFE_RIGHT),
                     _ => None,
                 };
 
@@ -76,7 +293,33 @@ pub(crate) fn bevy_rl_control_request(
             }
         }
 
-        control_agents(actions, query_actors, collision_events, event_gun_shot);
+        // With `sticky_action_prob` repeat the previous action instead of the new one
+        if settings.sticky_action_prob > 0.0 {
+            for (action, last_action) in actions.iter_mut().zip(last_actions.0.iter()) {
+                if env_rng.0.gen::<f32>() < settings.sticky_action_prob {
+                    *action = last_action.clone();
+                }
+            }
+        }
+        last_actions.0 = actions.clone();
+
+        // Charge the cost of actions that are applied, after sticky actions;
+        // dead agents ignore their actions
+        for (_, _, index, actor) in query_actors.iter() {
+            if actor.health == 0 {
+                continue;
+            }
+            if let Some(Some(action)) = actions.get(index.0) {
+                agent_rewards.0[index.0] += reward_functions.0.action(&settings, actor, action);
+            }
+        }
+
+        control_agents(
+            actions,
+            10.0 * settings.cell_size,
+            query_actors,
+            event_gun_shot,
+        );
         // Resume simulation (physics engine)
         rapier_configuration.physics_pipeline_active = true;
 
@@ -86,31 +329,86 @@ pub(crate) fn bevy_rl_control_request(
     }
 }
 
+/// Parse `WARP <x> <z> <rotation>` action string
+fn parse_warp(action: &str) -> Option<(f32, f32, f32)> {
+    let values: Vec<f32> = action
+        .split_whitespace()
+        .skip(1)
+        .map(|v| v.parse::<f32>())
+        .collect::<Result<_, _>>()
+        .ok()?;
+
+    match values[..] {
+        [x, z, rotation] => Some((x, z, rotation)),
+        _ => None,
+    }
+}
+
 /// Handle bevy_rl::EventReset
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn bevy_rl_reset_request(
     mut reset_event_reader: EventReader<EventReset>,
     mut commands: Commands,
     mut walls: Query<Entity, &Wall>,
+    floors: Query<Entity, With<Floor>>,
     mut players: Query<(Entity, &Actor)>,
     mut simulation_state: ResMut<NextState<SimulationState>>,
+    mut agent_rewards: ResMut<AgentRewards>,
+    mut reward_history: ResMut<RewardHistory>,
+    mut step_counter: ResMut<StepCounter>,
+    mut last_actions: ResMut<LastActions>,
     ai_gym_state: Res<AIGymState<Actions, EnvironmentState>>,
 ) {
     if reset_event_reader.iter().count() == 0 {
         return;
     }
 
+    last_actions.0.iter_mut().for_each(|a| *a = None);
+    agent_rewards.0.iter_mut().for_each(|r| *r = 0.0);
+    *reward_history = RewardHistory::default();
+    step_counter.0 = 0;
+
     for e in walls.iter_mut() {
         commands.entity(e).despawn_recursive();
     }
 
+    for e in floors.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+
     for (e, _) in players.iter_mut() {
         commands.entity(e).despawn_recursive();
     }
 
     simulation_state.set(SimulationState::Initializing);
 
-    let ai_gym_state = ai_gym_state.lock().unwrap();
-    ai_gym_state.send_reset_result(true);
+    // Clears terminations and rewards of the previous episode and answers `/reset`
+    let mut ai_gym_state = ai_gym_state.lock().unwrap();
+    ai_gym_state.reset();
+}
+
+/// Pass rewards of the current step to bevy_rl, applying `GameSettings::normalize_rewards`
+/// and `GameSettings::reward_clip`
+pub(crate) fn collect_rewards(
+    ai_gym_state: Res<AIGymState<Actions, EnvironmentState>>,
+    agent_rewards: Res<AgentRewards>,
+    reward_normalizer: Res<RewardNormalizer>,
+    settings: Res<GameSettings>,
+) {
+    let mut ai_gym_state = ai_gym_state.lock().unwrap();
+
+    for (i, reward) in agent_rewards.0.iter().enumerate() {
+        let reward = if settings.normalize_rewards {
+            reward_normalizer.normalize(*reward)
+        } else {
+            *reward
+        };
+        let reward = match settings.reward_clip {
+            Some((min, max)) => reward.max(min).min(max),
+            None => reward,
+        };
+        ai_gym_state.set_reward(i, reward);
+    }
 }
 
 /// Handle EventRoundOver
@@ -132,3 +430,183 @@ pub(crate) fn event_round_over(
 
     pause_event_writer.send(EventPause);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reward_normalizer_tracks_mean_and_variance() {
+        let mut reward_normalizer = RewardNormalizer::default();
+        for reward in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            reward_normalizer.update(reward);
+        }
+
+        assert!((reward_normalizer.mean - 5.0).abs() < 1e-9);
+        assert!((reward_normalizer.variance() - 32.0 / 7.0).abs() < 1e-9);
+        assert!(reward_normalizer.normalize(5.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn non_finite_rewards_serialize_to_valid_json() {
+        let env_state = EnvironmentState {
+            raw_rewards: vec![f32::NAN, f32::INFINITY, 1.0],
+            ..default()
+        };
+        let json = serde_json::to_string(&env_state).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(value["raw_rewards"], serde_json::json!([null, null, 1.0]));
+    }
+
+    #[test]
+    fn reset_clears_terminations() {
+        let mut app = App::new();
+        app.add_state::<SimulationState>()
+            .add_event::<EventReset>()
+            .insert_resource(AgentRewards(vec![1.0, 2.0]))
+            .init_resource::<RewardHistory>()
+            .init_resource::<StepCounter>()
+            .insert_resource(LastActions(vec![None, None]))
+            .insert_resource(AIGymState::<Actions, EnvironmentState>::new(
+                AIGymSettings {
+                    num_agents: 2,
+                    ..default()
+                },
+            ))
+            .add_system(bevy_rl_reset_request);
+
+        let ai_gym_state = app
+            .world
+            .resource::<AIGymState<Actions, EnvironmentState>>()
+            .clone();
+        ai_gym_state.lock().unwrap().set_terminated(1, true);
+
+        app.world.send_event(EventReset);
+        app.update();
+
+        assert_eq!(
+            ai_gym_state.lock().unwrap().terminations,
+            vec![false, false]
+        );
+        assert_eq!(app.world.resource::<AgentRewards>().0, vec![0.0, 0.0]);
+    }
+
+    fn test_actor(name: &str, health: u16) -> Actor {
+        Actor {
+            position: (0.0, 0.0),
+            rotation: 0.0,
+            name: name.to_string(),
+            health,
+            regenerating: false,
+            shots: 0,
+            hits: 0,
+            last_damager: None,
+            kills: 0,
+        }
+    }
+
+    /// Step and reset an episode of two agents through the gym request handlers
+    #[test]
+    fn gym_requests_drive_rewards_and_terminations() {
+        let settings = GameSettings {
+            survival_reward: 0.1,
+            action_cost: ActionCost {
+                movement: 0.25,
+                ..default()
+            },
+            reward_clip: Some((-0.2, 1.0)),
+            seed: Some(7),
+            ..default()
+        };
+        let mut app = App::new();
+        app.add_state::<SimulationState>()
+            .add_event::<EventControl>()
+            .add_event::<EventReset>()
+            .add_event::<EventGunShot>()
+            .add_event::<EventWarp>()
+            .add_event::<EventRoundOver>()
+            .init_resource::<Time>()
+            .init_resource::<RapierConfiguration>()
+            .insert_resource(RoundTimer(Timer::from_seconds(60.0, TimerMode::Repeating)))
+            .insert_resource(AgentRewards(vec![0.0; 2]))
+            .insert_resource(LastActions(vec![None; 2]))
+            .init_resource::<RewardHistory>()
+            .init_resource::<StepCounter>()
+            .init_resource::<RewardNormalizer>()
+            .init_resource::<RewardFunctions>()
+            .insert_resource(EnvRng::new(settings.seed))
+            .insert_resource(settings)
+            .insert_resource(AIGymState::<Actions, EnvironmentState>::new(
+                AIGymSettings {
+                    num_agents: 2,
+                    ..default()
+                },
+            ))
+            .add_systems(
+                (
+                    bevy_rl_control_request,
+                    collect_rewards,
+                    check_termination,
+                    bevy_rl_reset_request,
+                )
+                    .chain(),
+            );
+        for (index, health) in [100, 0].into_iter().enumerate() {
+            app.world.spawn((
+                Transform::default(),
+                Velocity::default(),
+                AgentIndex(index),
+                test_actor(&format!("agent-{index}"), health),
+            ));
+        }
+        let ai_gym_state = app
+            .world
+            .resource::<AIGymState<Actions, EnvironmentState>>()
+            .clone();
+
+        app.world.send_event(EventControl(vec![
+            Some("FORWARD".to_string()),
+            Some("FORWARD".to_string()),
+        ]));
+        app.update();
+
+        {
+            let ai_gym_state = ai_gym_state.lock().unwrap();
+            // Survival reward minus movement cost, clipped; dead agents earn nothing
+            assert_eq!(ai_gym_state.rewards, vec![-0.15, 0.0]);
+            assert_eq!(ai_gym_state.terminations, vec![false, true]);
+        }
+        assert_eq!(app.world.resource::<StepCounter>().0, 1);
+
+        app.world
+            .send_event(EventControl(vec![Some("TURN_LEFT".to_string()), None]));
+        app.update();
+
+        assert_eq!(ai_gym_state.lock().unwrap().rewards, vec![0.1, 0.0]);
+
+        app.world.send_event(EventReset);
+        app.update();
+
+        {
+            let ai_gym_state = ai_gym_state.lock().unwrap();
+            assert_eq!(ai_gym_state.rewards, vec![0.0, 0.0]);
+            assert_eq!(ai_gym_state.terminations, vec![false, false]);
+        }
+        assert_eq!(app.world.resource::<StepCounter>().0, 0);
+        assert_eq!(app.world.query::<&Actor>().iter(&app.world).count(), 0);
+    }
+
+    #[test]
+    fn parse_warp_reads_pose() {
+        assert_eq!(parse_warp("WARP 12 18.5 1.57"), Some((12.0, 18.5, 1.57)));
+    }
+
+    #[test]
+    fn parse_warp_rejects_malformed_actions() {
+        assert_eq!(parse_warp("WARP"), None);
+        assert_eq!(parse_warp("WARP 1 2"), None);
+        assert_eq!(parse_warp("WARP 1 2 3 4"), None);
+        assert_eq!(parse_warp("WARP x 2 3"), None);
+    }
+}
@@ -2,9 +2,10 @@ use bevy::prelude::*;
 use bevy_mod_raycast::RaycastMesh;
 use bevy_rapier3d::prelude::*;
 
+use rand::{prelude::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
 
-use crate::{game::*, map};
+use crate::{game::*, map, settings::*};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Resource)]
 pub struct GameMap {
@@ -22,6 +23,18 @@ impl Default for GameMap {
 #[derive(Component)]
 pub(crate) struct Wall;
 
+/// Floor of the level, respawned with a new material on every reset
+#[derive(Component)]
+pub(crate) struct Floor;
+
+/// Wall moving back and forth between `from` and `to`, see `GameSettings::moving_walls`
+#[derive(Component)]
+pub(crate) struct MovingWall {
+    from: Vec3,
+    to: Vec3,
+    period: f32,
+}
+
 #[derive(Bundle)]
 struct WallBundle {
     #[bundle]
@@ -35,48 +48,124 @@ struct WallBundle {
 pub(crate) fn spawn_game_world(
     mut commands: Commands,
     game_map: Res<GameMap>,
-    walls: Query<Entity, With<Wall>>,
+    settings: Res<GameSettings>,
+    mut env_rng: ResMut<EnvRng>,
+    mut ambient_light: ResMut<AmbientLight>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    let size = 255.0 * 255.0;
-    let mesh = meshes.add(Mesh::from(shape::Plane {
-        size: (size as f32),
-        ..default()
-    }));
-
-    let white_material_handle = materials.add(Color::WHITE.into());
-
-    // spawn floor only once
-    if walls.iter().len() == 0 {
-        commands
-            .spawn(PbrBundle {
-                mesh,
-                material: white_material_handle.clone(),
-                ..Default::default()
-            })
-            .insert(RigidBody::Fixed)
-            .insert(Collider::cuboid(256.0, 1.0, 256.0));
+    let cell_size = settings.cell_size;
+    let size = 255.0 * 255.0 * cell_size;
+    let mesh = meshes.add(Mesh::from(shape::Plane { size, ..default() }));
+
+    // Domain randomization: pick floor and wall colors from `GameSettings::color_pool`
+    let mut random_color = || match settings.color_pool.choose(&mut env_rng.0) {
+        Some([r, g, b]) => Color::rgb(*r, *g, *b),
+        None => Color::WHITE,
+    };
+    let floor_material_handle = materials.add(random_color().into());
+    let wall_material_handle = materials.add(random_color().into());
+
+    // Photometric randomization, see `GameSettings::brightness_jitter`
+    if let Some((min, max)) = settings.brightness_jitter {
+        ambient_light.brightness = settings.ambient_brightness * env_rng.0.gen_range(min..=max);
     }
 
-    let wall_mesh = meshes.add(Mesh::from(shape::Cube { size: 2.0 }));
+    commands
+        .spawn(PbrBundle {
+            mesh,
+            material: floor_material_handle,
+            ..Default::default()
+        })
+        .insert(RigidBody::Fixed)
+        .insert(Collider::cuboid(256.0 * cell_size, 1.0, 256.0 * cell_size))
+        .insert(Floor);
+
+    let wall_mesh = meshes.add(Mesh::from(shape::Box::new(
+        2.0 * cell_size,
+        2.0,
+        2.0 * cell_size,
+    )));
     let walls_iter: Vec<WallBundle> = game_map
         .walls
         .iter()
         .map(|(x, z)| WallBundle {
             pbr_pundle: PbrBundle {
                 mesh: wall_mesh.clone(),
-                material: white_material_handle.clone(),
-                transform: Transform::from_translation(Vec3::new(*x as f32, 1.0, *z as f32)),
+                material: wall_material_handle.clone(),
+                transform: Transform::from_translation(Vec3::new(
+                    *x as f32 * cell_size,
+                    1.0,
+                    *z as f32 * cell_size,
+                )),
                 global_transform: GlobalTransform::IDENTITY,
                 ..Default::default()
             },
             rigid_body: RigidBody::Fixed,
-            collider: Collider::cuboid(1.0, 1.0, 1.0),
+            collider: Collider::cuboid(cell_size, 1.0, cell_size),
             raycast_marker: RaycastMesh::<RaycastMarker>::default(),
             wall: Wall,
         })
         .collect();
 
     commands.spawn_batch(walls_iter);
+
+    // Moving walls are kinematic so that they push agents out of their way
+    for path in settings.moving_walls.iter() {
+        let from = Vec3::new(path.from.0 * cell_size, 1.0, path.from.1 * cell_size);
+        let to = Vec3::new(path.to.0 * cell_size, 1.0, path.to.1 * cell_size);
+
+        commands.spawn((
+            WallBundle {
+                pbr_pundle: PbrBundle {
+                    mesh: wall_mesh.clone(),
+                    material: wall_material_handle.clone(),
+                    transform: Transform::from_translation(from),
+                    ..Default::default()
+                },
+                rigid_body: RigidBody::KinematicPositionBased,
+                collider: Collider::cuboid(cell_size, 1.0, cell_size),
+                raycast_marker: RaycastMesh::<RaycastMarker>::default(),
+                wall: Wall,
+            },
+            MovingWall {
+                from,
+                to,
+                period: path.period,
+            },
+        ));
+    }
+}
+
+/// Fraction of the way from `from` to `to` after `elapsed` seconds, going there and back
+/// once per `period`
+fn moving_wall_progress(elapsed: f32, period: f32) -> f32 {
+    let phase = (elapsed / period).fract();
+    1.0 - (2.0 * phase - 1.0).abs()
+}
+
+pub(crate) fn move_walls(
+    round_timer: Res<RoundTimer>,
+    mut wall_query: Query<(&MovingWall, &mut Transform)>,
+) {
+    let elapsed = round_timer.0.elapsed_secs();
+
+    for (moving_wall, mut transform) in wall_query.iter_mut() {
+        let progress = moving_wall_progress(elapsed, moving_wall.period);
+        transform.translation = moving_wall.from.lerp(moving_wall.to, progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_wall_goes_there_and_back() {
+        assert_eq!(moving_wall_progress(0.0, 4.0), 0.0);
+        assert_eq!(moving_wall_progress(1.0, 4.0), 0.5);
+        assert_eq!(moving_wall_progress(2.0, 4.0), 1.0);
+        assert_eq!(moving_wall_progress(3.0, 4.0), 0.5);
+        assert_eq!(moving_wall_progress(4.0, 4.0), 0.0);
+    }
 }
@@ -0,0 +1,31 @@
+//! Multi-agent FPS gym environment for bevy_rl.
+//!
+//! `build_game_app` builds the bevy `App` of the environment; embedders can add their own
+//! systems and resources before running it, e.g. replace `RewardFunctions` to change rewards.
+
+mod actions;
+mod actors;
+mod benchmark;
+#[cfg(feature = "client")]
+pub mod client;
+mod control;
+mod events;
+mod game;
+mod gym;
+mod level;
+mod map;
+mod replay;
+mod rewards;
+mod settings;
+
+pub use actions::Actions;
+pub use actors::Actor;
+pub use game::{build_game_app, gym_settings};
+pub use gym::EnvironmentState;
+pub use level::GameMap;
+pub use replay::ActionLog;
+pub use rewards::{DefaultRewardFunction, RewardFunction, RewardFunctions};
+pub use settings::{
+    ActionCost, ActorCollider, Fog, GameSettings, HealthRegen, MovingWallPath, ObsFilter,
+    PreviewFilter, SpawnExclusionZone, SpawnPose, TonemappingMode, TrackingTarget, ViewBob,
+};
@@ -1,24 +1,80 @@
-mod actions;
-mod actors;
-mod control;
-mod events;
-mod game;
-mod gym;
-mod level;
-mod map;
-
 use clap::Parser;
+use serde::Serialize;
+
+use bevy_rl_shooter::{build_game_app, gym_settings, ActionLog, GameSettings};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None, )]
 struct Args {
     #[clap(short, long, default_value = "train")]
     mode: String,
+    #[clap(short, long)]
+    config: Option<String>,
+    /// Action log replayed in `--mode replay`
+    #[clap(short, long)]
+    replay: Option<String>,
+    /// Print the effective settings, including bevy_rl gym settings, as JSON and exit
+    #[clap(long)]
+    print_config: bool,
+}
+
+/// Output of `--print-config`: game settings with bevy_rl gym settings under `gym`
+#[derive(Serialize)]
+struct PrintedConfig<'a> {
+    #[serde(flatten)]
+    settings: &'a GameSettings,
+    gym: PrintedGymSettings,
+}
+
+#[derive(Serialize)]
+struct PrintedGymSettings {
+    width: u32,
+    height: u32,
+    num_agents: u32,
+    pause_interval: f32,
 }
 
 fn main() {
     let args = Args::parse();
 
-    let mut bevy_app = game::build_game_app(args.mode);
+    let settings = match args.config {
+        Some(path) => GameSettings::from_file(&path).unwrap_or_else(|error| {
+            eprintln!("Failed to load settings: {error}");
+            std::process::exit(1);
+        }),
+        None => GameSettings::default(),
+    };
+
+    if args.print_config {
+        let gym = gym_settings();
+        let config = PrintedConfig {
+            settings: &settings,
+            gym: PrintedGymSettings {
+                width: gym.width,
+                height: gym.height,
+                num_agents: gym.num_agents,
+                pause_interval: gym.pause_interval,
+            },
+        };
+        println!("{}", serde_json::to_string_pretty(&config).unwrap());
+        return;
+    }
+
+    let replay = match (args.mode.as_str(), args.replay) {
+        ("replay", Some(path)) => Some(ActionLog::from_file(&path).unwrap_or_else(|error| {
+            eprintln!("Failed to load action log: {error}");
+            std::process::exit(1);
+        })),
+        ("replay", None) => {
+            eprintln!("--mode replay requires --replay <log>");
+            std::process::exit(1);
+        }
+        _ => None,
+    };
+
+    let mut bevy_app = build_game_app(args.mode, settings, replay).unwrap_or_else(|error| {
+        eprintln!("Invalid settings: {error}");
+        std::process::exit(1);
+    });
     bevy_app.run();
 }
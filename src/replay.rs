@@ -0,0 +1,61 @@
+use bevy::{app::AppExit, prelude::*};
+use bevy_rl::*;
+use serde::Deserialize;
+
+use crate::{actions::*, gym::EnvironmentState};
+
+/// Action log replayed in `--mode replay`
+#[derive(Deserialize, Debug)]
+pub struct ActionLog {
+    /// Seed the log was recorded with, compared with `GameSettings::seed`
+    pub(crate) seed: Option<u64>,
+    /// Actions of every agent per step, in the format of `/step`
+    pub(crate) steps: Vec<Vec<Option<String>>>,
+}
+
+impl ActionLog {
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        serde_json::from_str(&contents).map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+/// Progress of `--mode replay` run
+#[derive(Resource)]
+pub(crate) struct Replay {
+    pub(crate) log: ActionLog,
+    pub(crate) completed: usize,
+}
+
+/// Drive agents with recorded actions instead of waiting for REST API requests
+pub(crate) fn replay_control(
+    mut replay: ResMut<Replay>,
+    mut pause_event_reader: EventReader<EventPause>,
+    mut control_event_writer: EventWriter<EventControl>,
+    mut app_exit_writer: EventWriter<AppExit>,
+    ai_gym_state: Res<AIGymState<Actions, EnvironmentState>>,
+) {
+    if pause_event_reader.iter().count() == 0 {
+        return;
+    }
+
+    if replay.completed == replay.log.steps.len() {
+        println!("replayed steps: {}", replay.completed);
+        app_exit_writer.send(AppExit);
+        return;
+    }
+
+    let num_agents = ai_gym_state.lock().unwrap().settings.num_agents as usize;
+    let mut actions = replay.log.steps[replay.completed].clone();
+    if actions.len() != num_agents {
+        eprintln!(
+            "Replay diverged: step {} has actions for {} agents, environment has {num_agents}",
+            replay.completed,
+            actions.len()
+        );
+        actions.truncate(num_agents);
+    }
+
+    control_event_writer.send(EventControl(actions));
+    replay.completed += 1;
+}
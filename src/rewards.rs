@@ -0,0 +1,256 @@
+use bevy::prelude::*;
+
+use crate::{actions::Actions, actors::Actor, settings::GameSettings};
+
+/// Reward shaping of game events; swap the `RewardFunctions` resource to change it
+pub trait RewardFunction: Send + Sync {
+    /// Reward of `shooter` for killing `victim`
+    fn kill(&self, settings: &GameSettings, shooter: &Actor, victim: &Actor) -> f32;
+    /// Reward of `actor` on the step it dies
+    fn death(&self, settings: &GameSettings, actor: &Actor) -> f32;
+    /// Reward of `actor` for every step it takes an action; `nearest_enemy` is the closest
+    /// other living agent
+    fn step(&self, settings: &GameSettings, actor: &Actor, nearest_enemy: Option<&Actor>) -> f32;
+    /// Reward of `actor` for applying `action` on a step
+    fn action(&self, settings: &GameSettings, actor: &Actor, action: &Actions) -> f32;
+    /// Reward of `shooter` for a shot, `hit` if it damaged another agent; the shot is already
+    /// counted in `shooter.shots` and `shooter.hits`
+    fn shot(&self, settings: &GameSettings, shooter: &Actor, hit: bool) -> f32;
+    /// Reward of `actor` for touching `other` during `seconds`
+    fn bump(&self, settings: &GameSettings, actor: &Actor, other: &Actor, seconds: f32) -> f32;
+    /// Reward of `actor` for keeping `target` in its line of sight during `seconds`
+    fn tracking(&self, settings: &GameSettings, actor: &Actor, target: &Actor, seconds: f32)
+        -> f32;
+}
+
+/// `kill_reward` per kill, `death_penalty` on death, `survival_reward` and `facing_reward` per
+/// step alive, `action_cost` per action, `accuracy_reward` per hit and as penalty per miss,
+/// `bump_reward` per second of contact and `tracking_reward` per second of line of sight
+pub struct DefaultRewardFunction;
+
+impl RewardFunction for DefaultRewardFunction {
+    fn kill(&self, settings: &GameSettings, _shooter: &Actor, _victim: &Actor) -> f32 {
+        settings.kill_reward
+    }
+
+    fn death(&self, settings: &GameSettings, _actor: &Actor) -> f32 {
+        -settings.death_penalty
+    }
+
+    fn step(&self, settings: &GameSettings, actor: &Actor, nearest_enemy: Option<&Actor>) -> f32 {
+        if actor.health == 0 {
+            return 0.0;
+        }
+
+        let facing = nearest_enemy.map_or(0.0, |enemy| facing_alignment(actor, enemy));
+        settings.survival_reward + settings.facing_reward * facing
+    }
+
+    fn action(&self, settings: &GameSettings, _actor: &Actor, action: &Actions) -> f32 {
+        let cost = &settings.action_cost;
+        let movement = Actions::FORWARD
+            | Actions::BACKWARD
+            | Actions::LEFT
+            | Actions::RIGHT
+            | Actions::STRAFE_LEFT
+            | Actions::STRAFE_RIGHT;
+        let turn =
+            Actions::TURN_LEFT | Actions::TURN_RIGHT | Actions::STRAFE_LEFT | Actions::STRAFE_RIGHT;
+
+        let mut reward = 0.0;
+        if action.intersects(movement) {
+            reward -= cost.movement;
+        }
+        if action.intersects(turn) {
+            reward -= cost.turn;
+        }
+        if action.contains(Actions::SHOOT) {
+            reward -= cost.shoot;
+        }
+        reward
+    }
+
+    fn shot(&self, settings: &GameSettings, _shooter: &Actor, hit: bool) -> f32 {
+        if hit {
+            settings.accuracy_reward
+        } else {
+            -settings.accuracy_reward
+        }
+    }
+
+    fn bump(&self, settings: &GameSettings, _actor: &Actor, _other: &Actor, seconds: f32) -> f32 {
+        settings.bump_reward * seconds
+    }
+
+    fn tracking(
+        &self,
+        settings: &GameSettings,
+        _actor: &Actor,
+        _target: &Actor,
+        seconds: f32,
+    ) -> f32 {
+        settings.tracking_reward * seconds
+    }
+}
+
+/// Closest living agent other than `actor`
+pub(crate) fn nearest_enemy<'a>(
+    actor: &Actor,
+    actors: impl Iterator<Item = &'a Actor>,
+) -> Option<&'a Actor> {
+    let distance = |other: &Actor| {
+        Vec2::new(other.position.0, other.position.1)
+            .distance(Vec2::new(actor.position.0, actor.position.1))
+    };
+
+    actors
+        .filter(|other| other.health > 0 && other.name != actor.name)
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+}
+
+/// Cosine of the angle between the heading of `actor` and the direction to `target`
+pub(crate) fn facing_alignment(actor: &Actor, target: &Actor) -> f32 {
+    let heading = Vec2::new(-actor.rotation.sin(), -actor.rotation.cos());
+    let direction = Vec2::new(
+        target.position.0 - actor.position.0,
+        target.position.1 - actor.position.1,
+    );
+
+    heading.dot(direction.normalize_or_zero())
+}
+
+/// Reward function used by game systems
+#[derive(Resource)]
+pub struct RewardFunctions(pub Box<dyn RewardFunction>);
+
+impl Default for RewardFunctions {
+    fn default() -> Self {
+        Self(Box::new(DefaultRewardFunction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::ActionCost;
+
+    fn actor(name: &str, health: u16) -> Actor {
+        Actor {
+            position: (0.0, 0.0),
+            rotation: 0.0,
+            name: name.to_string(),
+            health,
+            regenerating: false,
+            shots: 0,
+            hits: 0,
+            last_damager: None,
+            kills: 0,
+        }
+    }
+
+    #[test]
+    fn default_reward_function_follows_settings() {
+        let settings = GameSettings {
+            kill_reward: 5.0,
+            death_penalty: 2.0,
+            survival_reward: 0.1,
+            ..Default::default()
+        };
+        let reward_functions = RewardFunctions::default();
+
+        assert_eq!(
+            reward_functions
+                .0
+                .kill(&settings, &actor("shooter", 100), &actor("victim", 0)),
+            5.0
+        );
+        assert_eq!(
+            reward_functions.0.death(&settings, &actor("victim", 0)),
+            -2.0
+        );
+        assert_eq!(
+            reward_functions
+                .0
+                .step(&settings, &actor("shooter", 100), None),
+            0.1
+        );
+        assert_eq!(
+            reward_functions
+                .0
+                .step(&settings, &actor("victim", 0), None),
+            0.0
+        );
+    }
+
+    #[test]
+    fn facing_alignment_follows_heading() {
+        let mut shooter = actor("shooter", 100);
+        let mut target = actor("target", 100);
+        // Rotation 0 faces -z
+        target.position = (0.0, -5.0);
+        assert!((facing_alignment(&shooter, &target) - 1.0).abs() < 1e-6);
+
+        shooter.rotation = std::f32::consts::PI;
+        assert!((facing_alignment(&shooter, &target) + 1.0).abs() < 1e-6);
+
+        shooter.rotation = std::f32::consts::FRAC_PI_2;
+        assert!(facing_alignment(&shooter, &target).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nearest_enemy_skips_self_and_dead_agents() {
+        let shooter = actor("shooter", 100);
+        let mut dead = actor("dead", 0);
+        dead.position = (1.0, 0.0);
+        let mut near = actor("near", 100);
+        near.position = (3.0, 0.0);
+        let mut far = actor("far", 100);
+        far.position = (9.0, 0.0);
+        let actors = [shooter.clone(), dead, far, near];
+
+        assert_eq!(
+            nearest_enemy(&shooter, actors.iter()).map(|a| a.name.as_str()),
+            Some("near")
+        );
+    }
+
+    #[test]
+    fn default_reward_function_charges_action_cost() {
+        let settings = GameSettings {
+            action_cost: ActionCost {
+                movement: 0.25,
+                turn: 0.5,
+                shoot: 1.0,
+            },
+            ..Default::default()
+        };
+        let reward_functions = RewardFunctions::default();
+        let shooter = actor("shooter", 100);
+
+        assert_eq!(
+            reward_functions
+                .0
+                .action(&settings, &shooter, &Actions::IDLE),
+            0.0
+        );
+        assert_eq!(
+            reward_functions
+                .0
+                .action(&settings, &shooter, &(Actions::FORWARD | Actions::SHOOT)),
+            -1.25
+        );
+    }
+
+    #[test]
+    fn default_reward_function_shapes_accuracy() {
+        let settings = GameSettings {
+            accuracy_reward: 0.5,
+            ..Default::default()
+        };
+        let reward_functions = RewardFunctions::default();
+        let shooter = actor("shooter", 100);
+
+        assert_eq!(reward_functions.0.shot(&settings, &shooter, true), 0.5);
+        assert_eq!(reward_functions.0.shot(&settings, &shooter, false), -0.5);
+    }
+}
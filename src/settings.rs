@@ -0,0 +1,525 @@
+use bevy::{core_pipeline::tonemapping::Tonemapping, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::level::GameMap;
+
+/// Collision shape of an actor's rigid body
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ActorCollider {
+    Ball { radius: f32 },
+    Capsule { half_height: f32, radius: f32 },
+}
+
+/// Resampling filter used when downscaling agent views
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ObsFilter {
+    Nearest,
+    Triangle,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ObsFilter> for image::imageops::FilterType {
+    fn from(filter: ObsFilter) -> Self {
+        match filter {
+            ObsFilter::Nearest => Self::Nearest,
+            ObsFilter::Triangle => Self::Triangle,
+            ObsFilter::Gaussian => Self::Gaussian,
+            ObsFilter::Lanczos3 => Self::Lanczos3,
+        }
+    }
+}
+
+/// Sampler filtering of the preview window
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewFilter {
+    Nearest,
+    Linear,
+}
+
+/// Tone curve of agent cameras
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TonemappingMode {
+    None,
+    Reinhard,
+    ReinhardLuminance,
+    AcesFitted,
+    Agx,
+    TonyMcMapface,
+    BlenderFilmic,
+}
+
+impl From<TonemappingMode> for Tonemapping {
+    fn from(mode: TonemappingMode) -> Self {
+        match mode {
+            TonemappingMode::None => Self::None,
+            TonemappingMode::Reinhard => Self::Reinhard,
+            TonemappingMode::ReinhardLuminance => Self::ReinhardLuminance,
+            TonemappingMode::AcesFitted => Self::AcesFitted,
+            TonemappingMode::Agx => Self::AgX,
+            TonemappingMode::TonyMcMapface => Self::TonyMcMapface,
+            TonemappingMode::BlenderFilmic => Self::BlenderFilmic,
+        }
+    }
+}
+
+/// Exponential distance fog applied to agent cameras
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fog {
+    pub color: [f32; 3],
+    pub density: f32,
+}
+
+/// Vertical camera oscillation while an agent moves
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ViewBob {
+    /// Height of the oscillation
+    pub amplitude: f32,
+    /// Oscillations per second at full speed
+    pub frequency: f32,
+}
+
+/// Reward subtracted per step for each category of applied action
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ActionCost {
+    /// Any of the move and strafe actions
+    pub movement: f32,
+    /// `TURN_LEFT` or `TURN_RIGHT`
+    pub turn: f32,
+    pub shoot: f32,
+}
+
+/// Health regeneration after a period without taking damage
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HealthRegen {
+    /// Health points restored per second
+    pub rate: f32,
+    /// Seconds without damage before regeneration starts
+    pub grace_period: f32,
+}
+
+/// Wall moving back and forth between two map coordinates
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MovingWallPath {
+    pub from: (f32, f32),
+    pub to: (f32, f32),
+    /// Seconds of a full round trip
+    pub period: f32,
+}
+
+/// Agents whose line of sight earns `GameSettings::tracking_reward`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackingTarget {
+    /// Any living agent
+    Any,
+    /// Only the nearest living agent
+    Nearest,
+}
+
+/// Position on the map and heading an agent spawns with
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpawnPose {
+    pub x: f32,
+    pub z: f32,
+    pub rotation: f32,
+}
+
+/// Rectangle of map coordinates, bounds inclusive, where agents never spawn randomly
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpawnExclusionZone {
+    pub x_min: usize,
+    pub z_min: usize,
+    pub x_max: usize,
+    pub z_max: usize,
+}
+
+impl SpawnExclusionZone {
+    pub(crate) fn contains(&self, (x, z): (usize, usize)) -> bool {
+        (self.x_min..=self.x_max).contains(&x) && (self.z_min..=self.z_max).contains(&z)
+    }
+}
+
+/// Game settings on top of `bevy_rl::AIGymSettings`.
+/// Loaded from a JSON file passed with `--config`; missing fields fall back to defaults and
+/// unknown fields are rejected.
+/// Fields are public so embedders can build settings in code, e.g. starting from `default()`.
+#[derive(Serialize, Deserialize, Debug, Clone, Resource)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameSettings {
+    /// Accept `WARP` actions that teleport an agent (scripted evaluation only)
+    pub allow_warp: bool,
+    /// Clip per-step rewards to `(min, max)` before they are returned by `/step`
+    pub reward_clip: Option<(f32, f32)>,
+    /// Embed a base64 JPEG thumbnail of every agent's view into `/state`
+    pub state_thumbnails: bool,
+    /// Filter used to downscale `state_thumbnails`
+    pub obs_filter: ObsFilter,
+    /// Subtracted from an agent's reward on the step it dies
+    pub death_penalty: f32,
+    /// Directory agent views are saved to when a `SNAPSHOT` file is created in it or F12 is pressed
+    pub snapshot_dir: Option<String>,
+    /// Number of steps taken in `--mode benchmark`
+    pub benchmark_steps: u32,
+    /// Collision shape of agents
+    pub actor_collider: ActorCollider,
+    /// Friction coefficient of agent colliders
+    pub actor_friction: f32,
+    /// Restitution (bounciness) coefficient of agent colliders
+    pub actor_restitution: f32,
+    /// Walls moving along a path, in addition to the static walls of the map
+    pub moving_walls: Vec<MovingWallPath>,
+    /// Physics substeps per frame
+    pub physics_substeps: usize,
+    /// Keep agents moving along walls they run into instead of stopping them
+    pub wall_sliding: bool,
+    /// Scale of map coordinates; also scales wall size and agent speed
+    pub cell_size: f32,
+    /// Colors walls and the floor are randomly painted with on every reset; white if empty
+    pub color_pool: Vec<[f32; 3]>,
+    /// Color of the ambient light, the only light of the scene
+    pub ambient_color: [f32; 3],
+    /// Brightness of the ambient light
+    pub ambient_brightness: f32,
+    /// Range of the factor ambient light brightness is randomly scaled by on every reset
+    pub brightness_jitter: Option<(f32, f32)>,
+    /// Render agent models with a bright unlit material so they stand out in observations
+    pub highlight_agents: bool,
+    /// Distance fog rendered into observations
+    pub fog: Option<Fog>,
+    /// Near clipping plane distance of agent cameras
+    pub camera_near: f32,
+    /// Far clipping plane distance of agent cameras
+    pub camera_far: f32,
+    /// View bob of agent cameras while moving
+    pub view_bob: Option<ViewBob>,
+    /// Tonemapping of agent cameras
+    pub tonemapping: TonemappingMode,
+    /// Sampler filtering of agent views scaled into the preview window; observations are
+    /// not affected
+    pub preview_filter: PreviewFilter,
+    /// Number of recent steps whose rewards are kept in `/state`
+    pub reward_history_len: usize,
+    /// Seed of the environment random number generator; random if not set
+    pub seed: Option<u64>,
+    /// Probability of repeating an agent's previous action instead of the new one
+    pub sticky_action_prob: f32,
+    /// Health agents spawn with, also the regeneration cap
+    pub starting_health: u16,
+    pub health_regen: Option<HealthRegen>,
+    /// Size of the preview window; observations keep AIGymSettings resolution
+    pub window_resolution: Option<(f32, f32)>,
+    /// Standardize rewards with their running mean and variance
+    pub normalize_rewards: bool,
+    /// Reward for killing another agent
+    pub kill_reward: f32,
+    /// Reward for a shot that damages another agent, and penalty for one that does not
+    pub accuracy_reward: f32,
+    /// Kills after which an agent succeeds and the episode ends
+    pub success_kills: Option<u32>,
+    /// Reward for every step an agent is alive
+    pub survival_reward: f32,
+    /// Reward per step scaled by how closely an agent faces its nearest living enemy
+    pub facing_reward: f32,
+    /// Reward per second an agent touches another living agent; negative to penalize contact
+    pub bump_reward: f32,
+    /// Reward per second the center of an agent's view is on a living target
+    pub tracking_reward: f32,
+    pub tracking_target: TrackingTarget,
+    /// Penalty for applied actions by category
+    pub action_cost: ActionCost,
+    /// Probability that a shot aimed at an agent hits at point-blank range
+    pub shot_accuracy: f32,
+    /// Distance at which hit probability and damage of shots are halved; no falloff if not set
+    pub shot_falloff_distance: Option<f32>,
+    /// Spawn poses of agents by index; agents without an entry spawn randomly
+    pub spawn_plan: Option<Vec<SpawnPose>>,
+    /// Map regions excluded from random spawning
+    pub spawn_exclusion_zones: Vec<SpawnExclusionZone>,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            allow_warp: false,
+            reward_clip: None,
+            state_thumbnails: false,
+            obs_filter: ObsFilter::Triangle,
+            death_penalty: 0.0,
+            snapshot_dir: None,
+            benchmark_steps: 1000,
+            actor_collider: ActorCollider::Ball { radius: 1.0 },
+            actor_friction: 0.5,
+            actor_restitution: 0.0,
+            moving_walls: Vec::new(),
+            physics_substeps: 1,
+            wall_sliding: false,
+            cell_size: 1.0,
+            color_pool: Vec::new(),
+            ambient_color: [1.0, 1.0, 1.0],
+            ambient_brightness: 0.05,
+            brightness_jitter: None,
+            highlight_agents: false,
+            fog: None,
+            camera_near: 0.1,
+            camera_far: 1000.0,
+            view_bob: None,
+            tonemapping: TonemappingMode::TonyMcMapface,
+            preview_filter: PreviewFilter::Linear,
+            reward_history_len: 0,
+            seed: None,
+            sticky_action_prob: 0.0,
+            starting_health: 100,
+            health_regen: None,
+            window_resolution: None,
+            normalize_rewards: false,
+            kill_reward: 10.0,
+            accuracy_reward: 0.0,
+            success_kills: None,
+            survival_reward: 0.0,
+            facing_reward: 0.0,
+            bump_reward: 0.0,
+            tracking_reward: 0.0,
+            tracking_target: TrackingTarget::Any,
+            action_cost: ActionCost::default(),
+            shot_accuracy: 1.0,
+            shot_falloff_distance: None,
+            spawn_plan: None,
+            spawn_exclusion_zones: Vec::new(),
+        }
+    }
+}
+
+impl GameSettings {
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        Self::from_json(&contents).map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// Parse settings, ignoring the `gym` block `--print-config` adds so its output can be
+    /// loaded back
+    pub(crate) fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let mut value: serde_json::Value = serde_json::from_str(json)?;
+        if let Some(object) = value.as_object_mut() {
+            object.remove("gym");
+        }
+        serde_json::from_value(value)
+    }
+
+    /// Check for configurations that would fail later in game systems
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some((min, max)) = self.reward_clip {
+            if min > max {
+                return Err(format!("reward_clip: min {min} is greater than max {max}"));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.sticky_action_prob) {
+            return Err(format!(
+                "sticky_action_prob: {} is not a probability",
+                self.sticky_action_prob
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.shot_accuracy) {
+            return Err(format!(
+                "shot_accuracy: {} is not a probability",
+                self.shot_accuracy
+            ));
+        }
+        if let Some(distance) = self.shot_falloff_distance {
+            if distance <= 0.0 {
+                return Err(format!(
+                    "shot_falloff_distance: {distance} must be positive"
+                ));
+            }
+        }
+        if self.starting_health == 0 {
+            return Err("starting_health: must be positive".to_string());
+        }
+        if self.success_kills == Some(0) {
+            return Err("success_kills: must be positive".to_string());
+        }
+        if self.benchmark_steps == 0 {
+            return Err("benchmark_steps: must be positive".to_string());
+        }
+        let collider_valid = match self.actor_collider {
+            ActorCollider::Ball { radius } => radius > 0.0,
+            ActorCollider::Capsule {
+                half_height,
+                radius,
+            } => half_height > 0.0 && radius > 0.0,
+        };
+        if !collider_valid {
+            return Err(format!(
+                "actor_collider: dimensions of {:?} must be positive",
+                self.actor_collider
+            ));
+        }
+        if self.actor_friction < 0.0 || self.actor_restitution < 0.0 {
+            return Err(
+                "actor_friction, actor_restitution: coefficients must not be negative".to_string(),
+            );
+        }
+        if let Some(path) = self.moving_walls.iter().find(|path| path.period <= 0.0) {
+            return Err(format!("moving_walls: period of {path:?} must be positive"));
+        }
+        if self.physics_substeps == 0 {
+            return Err("physics_substeps: must be positive".to_string());
+        }
+        if self.cell_size <= 0.0 {
+            return Err(format!("cell_size: {} must be positive", self.cell_size));
+        }
+        if self.camera_near <= 0.0 || self.camera_near >= self.camera_far {
+            return Err(format!(
+                "camera_near, camera_far: {} and {} must satisfy 0 < near < far",
+                self.camera_near, self.camera_far
+            ));
+        }
+        if self.ambient_brightness < 0.0 {
+            return Err(format!(
+                "ambient_brightness: {} is negative",
+                self.ambient_brightness
+            ));
+        }
+        if let Some((min, max)) = self.brightness_jitter {
+            if min < 0.0 || min > max {
+                return Err(format!(
+                    "brightness_jitter: [{min}, {max}] is not a valid range of factors"
+                ));
+            }
+        }
+        if let Some(fog) = &self.fog {
+            if fog.density < 0.0 {
+                return Err(format!("fog: density {} is negative", fog.density));
+            }
+        }
+        if let Some(view_bob) = &self.view_bob {
+            if view_bob.amplitude < 0.0 || view_bob.frequency < 0.0 {
+                return Err("view_bob: amplitude and frequency must not be negative".to_string());
+            }
+        }
+        if let Some(health_regen) = &self.health_regen {
+            if health_regen.rate < 0.0 || health_regen.grace_period < 0.0 {
+                return Err("health_regen: rate and grace_period must not be negative".to_string());
+            }
+        }
+        if let Some((width, height)) = self.window_resolution {
+            if width <= 0.0 || height <= 0.0 {
+                return Err(format!(
+                    "window_resolution: {width}x{height} is not a valid window size"
+                ));
+            }
+        }
+        if let Some(spawn_plan) = &self.spawn_plan {
+            if let Some(pose) = spawn_plan
+                .iter()
+                .find(|p| !(p.x.is_finite() && p.z.is_finite() && p.rotation.is_finite()))
+            {
+                return Err(format!("spawn_plan: {pose:?} is not a valid pose"));
+            }
+        }
+        if let Some(zone) = self
+            .spawn_exclusion_zones
+            .iter()
+            .find(|zone| zone.x_min > zone.x_max || zone.z_min > zone.z_max)
+        {
+            return Err(format!(
+                "spawn_exclusion_zones: {zone:?} has min greater than max"
+            ));
+        }
+        if self.spawn_candidates(&GameMap::default()).is_empty() {
+            return Err("spawn_exclusion_zones: no empty space left to spawn in".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Whether an agent with `kills` kills has reached `success_kills`
+    pub(crate) fn is_success(&self, kills: u32) -> bool {
+        self.success_kills
+            .is_some_and(|success_kills| kills >= success_kills)
+    }
+
+    /// Tiles of the map's empty space outside `spawn_exclusion_zones`
+    pub(crate) fn spawn_candidates(&self, game_map: &GameMap) -> Vec<(usize, usize)> {
+        game_map
+            .empty_space
+            .iter()
+            .filter(|tile| {
+                !self
+                    .spawn_exclusion_zones
+                    .iter()
+                    .any(|zone| zone.contains(**tile))
+            })
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_settings_are_valid() {
+        assert!(GameSettings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn missing_fields_fall_back_to_defaults() {
+        let settings: GameSettings = serde_json::from_str(r#"{"kill_reward": 5.0}"#).unwrap();
+
+        assert_eq!(settings.kill_reward, 5.0);
+        assert_eq!(settings.starting_health, 100);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(GameSettings::from_json(r#"{"kill_rewrad": 5.0}"#).is_err());
+    }
+
+    #[test]
+    fn printed_gym_settings_are_ignored() {
+        let settings =
+            GameSettings::from_json(r#"{"kill_reward": 5.0, "gym": {"num_agents": 16}}"#).unwrap();
+
+        assert_eq!(settings.kill_reward, 5.0);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let settings = GameSettings {
+            reward_clip: Some((1.0, -1.0)),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = GameSettings {
+            sticky_action_prob: 1.5,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = GameSettings {
+            spawn_exclusion_zones: vec![SpawnExclusionZone {
+                x_min: 0,
+                z_min: 0,
+                x_max: usize::MAX,
+                z_max: usize::MAX,
+            }],
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = GameSettings {
+            camera_near: 10.0,
+            camera_far: 5.0,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+}